@@ -0,0 +1,625 @@
+//! Battery levels and the [`BatterySource`] trait they are read through.
+
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display};
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use async_stream::stream;
+use contracts::{ensures, requires};
+use futures::StreamExt;
+use futures::stream::LocalBoxStream;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::{debug, info, warn};
+
+/// A battery level in percent, from 0 to 100.
+#[derive(Clone, Debug, PartialOrd, Ord, Eq, PartialEq)]
+pub struct BatteryLevel(pub(crate) u8);
+
+impl BatteryLevel {
+    /// The level `percent`, which must be at most 100.
+    #[requires(percent.clone().try_into().is_ok_and(|v| v <= 100))]
+    #[ensures(ret.0 <= 100)]
+    pub fn new<P: TryInto<u8, Error = E> + Clone, E: Debug>(percent: P) -> Self {
+        let level = percent.try_into().expect("could not convert to u8");
+        BatteryLevel(level)
+    }
+
+    /// The level `percent`, or an error if it isn't from 0 to 100, e.g. for
+    /// user input.
+    pub fn try_new<P: TryInto<u8> + Display + Copy>(percent: P) -> Result<Self, InvalidLevel> {
+        match percent.try_into() {
+            Ok(level) if level <= 100 => Ok(BatteryLevel(level)),
+            _ => Err(InvalidLevel(percent.to_string())),
+        }
+    }
+
+    /// The level `percent`, or 100 if it is more.
+    pub const fn saturating(percent: u8) -> Self {
+        BatteryLevel(if percent > 100 { 100 } else { percent })
+    }
+
+    /// The level in percent.
+    #[ensures(ret <= 100)]
+    #[ensures(ret == self.0)]
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Display for BatteryLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
+/// As the plain number of percent.
+impl Serialize for BatteryLevel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.0)
+    }
+}
+
+/// From a number of percent, or a string such as `"15%"`, failing unless it's
+/// from 0 to 100.
+impl<'de> Deserialize<'de> for BatteryLevel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Percent {
+            Number(i64),
+            Text(String),
+        }
+
+        let percent = match Percent::deserialize(deserializer)? {
+            Percent::Number(percent) => percent,
+            Percent::Text(text) => text.trim().trim_end_matches('%').parse().map_err(|_| {
+                D::Error::custom(format!("{text:?} is not a percentage, such as 15%"))
+            })?,
+        };
+        BatteryLevel::try_new(percent).map_err(D::Error::custom)
+    }
+}
+
+/// A percentage given for a [`BatteryLevel`] that is out of range.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidLevel(String);
+
+impl Display for InvalidLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is not a level from 0 to 100%", self.0)
+    }
+}
+
+impl std::error::Error for InvalidLevel {}
+
+/// The level of a battery holding `current` out of `total`, in any units.
+///
+/// This rounds down, so a battery at 99.5% isn't reported full and the level
+/// never reads higher than the charge left. A reading over `total` counts as
+/// full, but a `total` of zero or less is an error.
+pub fn calc_battery_level(current: f32, total: f32) -> Result<BatteryLevel, anyhow::Error> {
+    if total.is_nan() || total <= 0.0 {
+        bail!("Full charge of {total} can't give a level");
+    }
+    // In f64, as charges in µWh are too precise for f32 to divide exactly.
+    let level = (f64::from(current) * 100.0 / f64::from(total)).floor();
+    Ok(BatteryLevel::new(level.clamp(0.0, 100.0) as u8))
+}
+
+/// Whether the batteries are charging, running the system or full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChargingState {
+    Charging,
+    Discharging,
+    /// Plugged in but no longer charging, so there's nothing to warn about.
+    Full,
+    /// Plugged in but holding its charge, e.g. at a charge limit, so not
+    /// draining either.
+    NotCharging,
+}
+
+/// The firmware's own classification of how charged the battery is, sorted
+/// from the most severe.
+#[derive(Clone, Copy, Debug, PartialOrd, Ord, PartialEq, Eq)]
+pub enum CapacityLevel {
+    Critical,
+    Low,
+    Normal,
+    High,
+    Full,
+}
+
+/// What a battery is, as its driver describes it, e.g. for bug reports.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BatteryInfo {
+    pub manufacturer: Option<String>,
+    pub model_name: Option<String>,
+    /// The chemistry, e.g. `Li-ion`.
+    pub technology: Option<String>,
+}
+
+impl Display for BatteryInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name: Vec<_> = [&self.manufacturer, &self.model_name]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if name.is_empty() {
+            write!(f, "unknown battery")?;
+        } else {
+            write!(f, "{}", name.join(" "))?;
+        }
+        match &self.technology {
+            Some(technology) => write!(f, " ({technology})"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Where battery readings come from, so the notification logic can run
+/// against something other than the real hardware.
+///
+/// Readings that not every battery can provide default to `None`.
+#[allow(async_fn_in_trait)]
+pub trait BatterySource {
+    /// Identifies the batteries currently present, empty while there are
+    /// none. When this changes the full charge must be read again.
+    fn present(&self) -> Vec<String>;
+
+    /// Whether any battery is charging.
+    async fn charging(&self) -> Result<bool, anyhow::Error>;
+
+    /// Whether any battery is discharging, i.e. the system is running on
+    /// battery power. Unlike `!charging()`, this is false for a battery that
+    /// is plugged in but full.
+    async fn discharging(&self) -> Result<bool, anyhow::Error>;
+
+    /// Whether the batteries are charging, discharging, full or holding.
+    /// Sources that can't tell the last two apart report either as holding.
+    async fn charging_state(&self) -> Result<ChargingState, anyhow::Error> {
+        Ok(if self.charging().await? {
+            ChargingState::Charging
+        } else if self.discharging().await? {
+            ChargingState::Discharging
+        } else {
+            ChargingState::NotCharging
+        })
+    }
+
+    /// The current charge, in the same units as [`BatterySource::energy_full`].
+    async fn energy_now(&self) -> Result<f32, anyhow::Error>;
+
+    /// The charge when full.
+    async fn energy_full(&self) -> Result<f32, anyhow::Error>;
+
+    /// A level reported directly by the battery, if it should be used instead
+    /// of the ratio of `energy_now` to `energy_full`.
+    async fn capacity(&self) -> Result<Option<BatteryLevel>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// The current charge in watt-hours, if the batteries report it as
+    /// energy rather than charge.
+    async fn energy_now_wh(&self) -> Result<Option<f32>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// The power flowing into or out of the batteries in watts.
+    async fn power_draw_watts(&self) -> Result<Option<f32>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// The battery voltage in volts.
+    async fn voltage(&self) -> Result<Option<f32>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// The current flowing into or out of the batteries in amps.
+    async fn current(&self) -> Result<Option<f32>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// Estimated time until empty, `None` while charging.
+    async fn time_to_empty(&self) -> Result<Option<Duration>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// Estimated time until full, `None` while not charging.
+    async fn time_to_full(&self) -> Result<Option<Duration>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// The full charge as a percentage of the design capacity.
+    async fn health_percent(&self) -> Result<Option<f32>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// How many charge cycles the most used battery has been through.
+    async fn cycle_count(&self) -> Result<Option<u32>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// The temperature of the hottest battery in degrees Celsius.
+    async fn temperature_c(&self) -> Result<Option<f32>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// What the first battery present is.
+    async fn info(&self) -> Result<Option<BatteryInfo>, anyhow::Error> {
+        Ok(None)
+    }
+
+    /// The firmware's classification of the most severe battery, `None` if
+    /// it doesn't give one.
+    async fn capacity_level(&self) -> Result<Option<CapacityLevel>, anyhow::Error> {
+        Ok(None)
+    }
+}
+
+/// How many polls the full charge is cached for before reading it again, as
+/// the kernel may recalibrate it after a full cycle.
+const FULL_REFRESH_POLLS: u32 = 20;
+
+/// The readings from one poll, taken together so that the level and the
+/// charging state describe the same moment.
+#[derive(Clone, Debug)]
+pub struct BatterySnapshot {
+    pub level: BatteryLevel,
+    pub charging_state: ChargingState,
+    /// Whether running on battery power, see [`BatterySource::discharging`].
+    pub discharging: bool,
+    /// `None` if the batteries don't report it or it couldn't be read.
+    pub power_draw_watts: Option<f32>,
+}
+
+impl BatterySnapshot {
+    /// Whether any battery is charging.
+    pub fn charging(&self) -> bool {
+        self.charging_state == ChargingState::Charging
+    }
+}
+
+/// The charge when full, cached between polls as it rarely changes.
+#[derive(Default)]
+struct FullCharge {
+    cached: Option<f32>,
+    polls_since_read: u32,
+}
+
+impl FullCharge {
+    /// The full charge, read again every [`FULL_REFRESH_POLLS`] polls.
+    async fn get<S: BatterySource>(&mut self, source: &S) -> Result<f32, anyhow::Error> {
+        self.polls_since_read += 1;
+        if let Some(cached) = self.cached
+            && self.polls_since_read >= FULL_REFRESH_POLLS
+        {
+            self.polls_since_read = 0;
+            // Keep the cached value if this fails, rather than skip a poll.
+            match source.energy_full().await {
+                Ok(full) if full != cached => {
+                    info!("Full charge changed from {cached} to {full}, recalibrated");
+                    self.cached = Some(full);
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Failed to re-read full battery level: {e:#}"),
+            }
+        }
+        match self.cached {
+            Some(full) => Ok(full),
+            None => {
+                let full = source
+                    .energy_full()
+                    .await
+                    .map_err(|e| e.context("Failed to get full battery level"))?;
+                self.polls_since_read = 0;
+                Ok(*self.cached.insert(full))
+            }
+        }
+    }
+
+    /// Reads the full charge again straight away, as the cached one seems
+    /// stale.
+    async fn reread<S: BatterySource>(&mut self, source: &S) -> Result<f32, anyhow::Error> {
+        let before = self.cached.take();
+        let full = self.get(source).await?;
+        if let Some(before) = before
+            && before != full
+        {
+            info!("Full charge changed from {before} to {full}, recalibrated");
+        }
+        Ok(full)
+    }
+}
+
+/// Reads the charging status, level and power draw back to back, so none of
+/// them is a poll behind the others. The status is read once, so whether
+/// it's discharging can't disagree with it.
+async fn read_battery_snapshot<S: BatterySource>(
+    source: &S,
+    full: &mut FullCharge,
+) -> Result<BatterySnapshot, anyhow::Error> {
+    let charging_state = source
+        .charging_state()
+        .await
+        .map_err(|e| e.context("Failed to get charging status"))?;
+    let discharging = charging_state == ChargingState::Discharging;
+    let level = match source
+        .capacity()
+        .await
+        .map_err(|e| e.context("Failed to get battery capacity"))?
+    {
+        Some(level) => level,
+        None => {
+            let mut total = full.get(source).await?;
+            let current = source
+                .energy_now()
+                .await
+                .map_err(|e| e.context("Failed to get current battery level"))?;
+            // A battery holding more than its full charge has likely been
+            // recalibrated since that was read.
+            if current > total {
+                debug!("Charge {current} is above the full charge {total}, re-reading it");
+                total = full.reread(source).await?;
+            }
+            calc_battery_level(current, total)?
+        }
+    };
+    Ok(BatterySnapshot {
+        level,
+        charging_state,
+        discharging,
+        power_draw_watts: source.power_draw_watts().await.unwrap_or(None),
+    })
+}
+
+/// Polls the batteries that are present, yielding `None` while none are and
+/// an error for any poll where they couldn't be read, so the caller can
+/// decide whether to carry on.
+pub fn battery_snapshot_stream<S: BatterySource>(
+    source: &S,
+) -> LocalBoxStream<'_, Result<Option<BatterySnapshot>, anyhow::Error>> {
+    Box::pin(stream! {
+        let mut last_present = Vec::new();
+        let mut full = FullCharge::default();
+
+        loop {
+            let present = source.present();
+            if present.is_empty() {
+                yield Ok(None);
+                continue;
+            }
+            // A swapped battery may hold a different charge when full.
+            if present != last_present {
+                full = FullCharge::default();
+                last_present = present;
+            }
+
+            yield read_battery_snapshot(source, &mut full).await.map(Some);
+        }
+    })
+}
+
+/// Averages the level of each snapshot from `snapshots` with up to
+/// `window - 1` before it, to smooth out jumps under load. The average starts
+/// afresh whenever the batteries go missing; errors are passed through
+/// without affecting it.
+pub fn smoothed_levels<'a>(
+    mut snapshots: LocalBoxStream<'a, Result<Option<BatterySnapshot>, anyhow::Error>>,
+    window: usize,
+) -> LocalBoxStream<'a, Result<Option<BatterySnapshot>, anyhow::Error>> {
+    if window <= 1 {
+        return snapshots;
+    }
+    Box::pin(stream! {
+        let mut recent = VecDeque::with_capacity(window);
+        while let Some(reading) = snapshots.next().await {
+            match reading {
+                Ok(Some(mut snapshot)) => {
+                    if recent.len() == window {
+                        recent.pop_front();
+                    }
+                    recent.push_back(snapshot.level.level() as usize);
+                    let sum: usize = recent.iter().sum();
+                    snapshot.level = BatteryLevel::new((sum + recent.len() / 2) / recent.len());
+                    yield Ok(Some(snapshot));
+                }
+                Ok(None) => {
+                    recent.clear();
+                    yield Ok(None);
+                }
+                Err(e) => yield Err(e),
+            }
+        }
+    })
+}
+
+/// The last few readings of a value, to tell how fast it changes.
+struct RateWindow {
+    window: usize,
+    samples: VecDeque<(Instant, f32)>,
+}
+
+impl RateWindow {
+    fn new(window: usize) -> RateWindow {
+        RateWindow {
+            window,
+            samples: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Records `value` as read at `at`, returning its change per hour since
+    /// the oldest reading kept, once there are enough of them.
+    fn record(&mut self, at: Instant, value: f32) -> Option<f32> {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((at, value));
+        if self.samples.len() < self.window {
+            return None;
+        }
+        let (since, from) = self.samples[0];
+        let hours = at.duration_since(since).as_secs_f32() / 3600.0;
+        (hours > 0.0).then(|| (value - from) / hours)
+    }
+
+    fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// How fast the level is falling, over the last few readings.
+pub struct DrainRate(RateWindow);
+
+impl DrainRate {
+    /// Measures the rate over `window` readings, at least two.
+    #[requires(window >= 2)]
+    pub fn new(window: usize) -> DrainRate {
+        DrainRate(RateWindow::new(window))
+    }
+
+    /// Records `level` as read at `at`, returning the drain in percent per
+    /// minute since the oldest reading kept, once there are enough of them.
+    pub fn record(&mut self, at: Instant, level: &BatteryLevel) -> Option<f32> {
+        self.0
+            .record(at, level.level() as f32)
+            .map(|per_hour| -per_hour / 60.0)
+    }
+
+    /// Forgets the readings, e.g. once charging.
+    pub fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+/// How fast the batteries are gaining energy, over the last few readings.
+pub struct ChargeRate(RateWindow);
+
+impl ChargeRate {
+    /// Measures the rate over `window` readings, at least two.
+    #[requires(window >= 2)]
+    pub fn new(window: usize) -> ChargeRate {
+        ChargeRate(RateWindow::new(window))
+    }
+
+    /// Records `energy_wh` as read at `at`, returning the net power going
+    /// into the batteries in watts since the oldest reading kept, negative
+    /// while they drain, once there are enough of them.
+    pub fn record(&mut self, at: Instant, energy_wh: f32) -> Option<f32> {
+        self.0.record(at, energy_wh)
+    }
+
+    /// Forgets the readings, e.g. once unplugged.
+    pub fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+/// A [`BatterySource`] replaying scripted readings, one per poll, for tests.
+/// Once they run out every reading fails, so e.g. `--max-failures` stops
+/// whatever is polling it.
+#[cfg(any(test, feature = "test-util"))]
+pub struct MockSource {
+    readings: Vec<(BatteryLevel, ChargingState)>,
+    /// How many polls have started, each asking which batteries are present.
+    polls: std::cell::Cell<usize>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl MockSource {
+    /// Replays `readings` of a level in percent and what the battery was
+    /// doing.
+    pub fn new(readings: impl IntoIterator<Item = (u8, ChargingState)>) -> MockSource {
+        MockSource {
+            readings: readings
+                .into_iter()
+                .map(|(percent, state)| (BatteryLevel::new(percent), state))
+                .collect(),
+            polls: std::cell::Cell::new(0),
+        }
+    }
+
+    fn reading(&self) -> Result<&(BatteryLevel, ChargingState), anyhow::Error> {
+        match self.polls.get().checked_sub(1) {
+            Some(poll) if poll < self.readings.len() => Ok(&self.readings[poll]),
+            _ => bail!("No reading scripted for poll {}", self.polls.get()),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl BatterySource for MockSource {
+    /// Starts the next poll.
+    fn present(&self) -> Vec<String> {
+        self.polls.set(self.polls.get() + 1);
+        vec!["mock".to_string()]
+    }
+
+    async fn charging(&self) -> Result<bool, anyhow::Error> {
+        Ok(self.reading()?.1 == ChargingState::Charging)
+    }
+
+    async fn discharging(&self) -> Result<bool, anyhow::Error> {
+        Ok(self.reading()?.1 == ChargingState::Discharging)
+    }
+
+    async fn charging_state(&self) -> Result<ChargingState, anyhow::Error> {
+        Ok(self.reading()?.1)
+    }
+
+    async fn energy_now(&self) -> Result<f32, anyhow::Error> {
+        Ok(self.reading()?.0.level() as f32)
+    }
+
+    async fn energy_full(&self) -> Result<f32, anyhow::Error> {
+        Ok(100.0)
+    }
+
+    async fn capacity(&self) -> Result<Option<BatteryLevel>, anyhow::Error> {
+        Ok(Some(self.reading()?.0.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        level: BatteryLevel,
+    }
+
+    #[test]
+    fn level_round_trips() {
+        for percent in [0, 15, 100] {
+            let config = Config {
+                level: BatteryLevel::new(percent),
+            };
+            let raw = toml::to_string(&config).unwrap();
+            assert_eq!(raw.trim(), format!("level = {percent}"));
+            assert_eq!(toml::from_str::<Config>(&raw).unwrap(), config);
+        }
+    }
+
+    #[test]
+    fn level_from_a_percentage() {
+        for raw in [r#"level = "15%""#, r#"level = " 15 ""#] {
+            let config: Config = toml::from_str(raw).unwrap();
+            assert_eq!(config.level, BatteryLevel::new(15));
+        }
+    }
+
+    #[test]
+    fn level_out_of_range() {
+        for raw in [
+            "level = 101",
+            "level = -1",
+            r#"level = "101%""#,
+            r#"level = "low""#,
+        ] {
+            assert!(toml::from_str::<Config>(raw).is_err(), "{raw}");
+        }
+    }
+}
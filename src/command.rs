@@ -0,0 +1,56 @@
+use std::process::Stdio;
+
+use battery_notifier::battery::BatteryLevel;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// Runs `command` with `sh -c` in the background so a slow command doesn't
+/// hold up polling, logging a warning if it can't be started or fails. The
+/// battery percentage is passed as `$1` and in `BATTERY_LEVEL`.
+pub fn spawn_shell(command: &str, level: &BatteryLevel) {
+    let level = level.level().to_string();
+    spawn(command, &[&level], &[("BATTERY_LEVEL", &level)]);
+}
+
+/// Runs `command` like [`spawn_shell`] for a notification that couldn't be
+/// shown, passing its summary as `$1` and in `BATTERY_SUMMARY` and its body as
+/// `$2` and in `BATTERY_BODY`.
+pub fn spawn_fallback(command: &str, summary: &str, body: &str) {
+    spawn(
+        command,
+        &[summary, body],
+        &[("BATTERY_SUMMARY", summary), ("BATTERY_BODY", body)],
+    );
+}
+
+fn spawn(command: &str, args: &[&str], envs: &[(&str, &str)]) {
+    let mut child = match Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .arg("sh")
+        .args(args)
+        .envs(envs.iter().copied())
+        .stdin(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to run `{command}`: {e}");
+            return;
+        }
+    };
+
+    let command = command.to_string();
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) if !status.success() => warn!("`{command}` failed with {status}"),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to wait for `{command}`: {e}"),
+        }
+    });
+}
+
+/// Logs the command [`spawn_shell`] would run, for `--dry-run`.
+pub fn log_shell(command: &str, level: &BatteryLevel) {
+    info!("Would run `{command}` with BATTERY_LEVEL={}", level.level());
+}
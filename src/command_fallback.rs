@@ -0,0 +1,58 @@
+//! Notifications that fall back to `--fallback-cmd` when they can't be shown,
+//! e.g. on a bare Wayland session with no notification server, rather than
+//! failing and stopping the daemon.
+
+use tracing::warn;
+
+use crate::command::spawn_fallback;
+use crate::notification::{Action, Message, Notifier};
+
+pub struct CommandFallback<N> {
+    notifier: N,
+    command: Option<String>,
+}
+
+impl<N: Notifier> CommandFallback<N> {
+    /// Shows notifications through `notifier`, running `command` for those it
+    /// fails to show, or only logging them without one.
+    pub fn new(notifier: N, command: Option<String>) -> CommandFallback<N> {
+        CommandFallback { notifier, command }
+    }
+}
+
+impl<N: Notifier> Notifier for CommandFallback<N> {
+    /// `None` for a notification that fell back.
+    type Handle = Option<N::Handle>;
+
+    async fn show(
+        &self,
+        message: &Message,
+        replaces: Option<&Option<N::Handle>>,
+    ) -> Result<Option<N::Handle>, anyhow::Error> {
+        let replaces = replaces.and_then(Option::as_ref);
+        match self.notifier.show(message, replaces).await {
+            Ok(handle) => Ok(Some(handle)),
+            Err(e) => {
+                warn!(
+                    "Failed to notify: {e:#}: {}: {}",
+                    message.summary, message.body
+                );
+                if let Some(command) = &self.command {
+                    spawn_fallback(command, &message.summary, &message.body);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    async fn close(&self, handle: &Option<N::Handle>) -> Result<(), anyhow::Error> {
+        match handle {
+            Some(handle) => self.notifier.close(handle).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn action(&self) -> Action {
+        self.notifier.action().await
+    }
+}
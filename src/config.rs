@@ -0,0 +1,1517 @@
+use std::env;
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, anyhow, bail};
+use battery_notifier::battery::BatteryLevel;
+use battery_notifier::sysfs::{
+    CapacitySource, ChargeSource, POWER_SUPPLY_DIR, UnknownStatus, system_batteries,
+};
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use toml::{Table, Value};
+use tracing::warn;
+
+use crate::APP_NAME;
+use crate::messages::{self, Messages};
+use crate::notification::Timeout;
+use crate::quiet_hours::QuietHours;
+
+const DEFAULT_BATTERY: &str = "BAT0";
+const CRITICAL_BATTERY_LEVEL: BatteryLevel = BatteryLevel::saturating(6);
+const LOW_BATTERY_LEVEL: BatteryLevel = BatteryLevel::saturating(15);
+/// How far above a warning level the battery must recover before that
+/// warning is re-armed, unless configured otherwise.
+const CLEAR_MARGIN: u8 = 5;
+/// Plays the freedesktop sound theme's critical battery sound.
+const CRITICAL_SOUND_CMD: &str = "canberra-gtk-play --id=battery-caution";
+const SUSPEND_CMD: &str = "systemctl suspend";
+const FULL_BATTERY_LEVEL: BatteryLevel = BatteryLevel::saturating(100);
+const CRITICAL_ICON: &str = "battery-caution";
+const LOW_ICON: &str = "battery-low";
+/// Long enough to notice a low battery warning without it lingering.
+const LOW_TIMEOUT: Timeout = Timeout::After(Duration::from_secs(10));
+const FULL_ICON: &str = "battery-full-charged";
+const HEALTH_WARN_LEVEL: u8 = 70;
+const TEMP_WARN_CELSIUS: u8 = 45;
+const TEMP_FREQUENCY: Duration = Duration::from_secs(10 * 60);
+const DRAIN_FREQUENCY: Duration = Duration::from_secs(30 * 60);
+const SLOW_CHARGE_FREQUENCY: Duration = Duration::from_secs(30 * 60);
+const SNOOZE: Duration = Duration::from_secs(15 * 60);
+const MAX_FAILURES: u32 = 10;
+/// Poll intervals used unless a fixed interval is configured: the first
+/// applies above the given level, falling through to the last.
+const ADAPTIVE_POLL: [(BatteryLevel, u64); 3] = [
+    (BatteryLevel::saturating(50), 5 * 60),
+    (BatteryLevel::saturating(20), 60),
+    (BatteryLevel::saturating(0), 15),
+];
+/// The shortest poll interval allowed.
+pub const MIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const CRITICAL_MINUTES: u64 = 10;
+const CRIT_FREQUENCY: Duration = Duration::from_secs(60);
+const LOW_FREQUENCY: Duration = Duration::from_secs(5 * 60);
+
+/// Command line flags. Anything left unset falls back to the config file and
+/// then to the built-in defaults.
+#[derive(Parser)]
+#[command(
+    version,
+    about,
+    after_help = "Exit status: 0 once stopped cleanly, 2 if no battery can be read, 3 for an \
+                  invalid command line or config file, 4 if the notification backend can't \
+                  be started, and 1 for anything else."
+)]
+pub struct Args {
+    /// Config file to read instead of ~/.config/battery-notifier/config.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Profile in the config file whose keys override the top-level ones,
+    /// from its `[profiles.NAME]` table [default: the config's `profile`]
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Print the battery health, its full charge as a percentage of its
+    /// design capacity, and exit
+    #[arg(long, group = "mode")]
+    health: bool,
+
+    /// Print the battery level, whether it's charging, the power draw and
+    /// the time remaining as a JSON object, e.g. for a status bar, and exit
+    #[arg(long, group = "mode")]
+    status: bool,
+
+    /// Include more detail, such as the voltage and current with --status,
+    /// or why each warning was or wasn't shown while monitoring
+    #[arg(long)]
+    verbose: bool,
+
+    /// Log the notifications that would be shown and the commands that would
+    /// be run, instead of showing or running them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Show the battery state and why warnings did or didn't fire in the
+    /// terminal, updated each poll, instead of notifying; logs are off unless
+    /// RUST_LOG is set
+    #[arg(long)]
+    watch: bool,
+
+    /// Print each threshold crossing and charging change to stdout as a line
+    /// of JSON, with its timestamp, event and level, instead of notifying;
+    /// logs go to stderr
+    #[arg(long, group = "mode")]
+    json_events: bool,
+
+    /// Fork into the background, detached from the terminal, with the logs
+    /// going to --log-file
+    #[arg(long)]
+    daemonize: bool,
+
+    /// File to write the process id to with --daemonize
+    #[arg(long, value_name = "PATH", requires = "daemonize")]
+    pidfile: Option<PathBuf>,
+
+    /// File to append the logs to with --daemonize [default:
+    /// ~/.local/state/battery-notifier/battery-notifier.log]
+    #[arg(long, value_name = "PATH", requires = "daemonize")]
+    log_file: Option<PathBuf>,
+
+    /// Print how many charge cycles the battery has been through and exit
+    #[arg(long, group = "mode")]
+    cycles: bool,
+
+    /// Wait until the battery falls to the low level while discharging, and
+    /// exit, e.g. `battery-notifier --until-low && save-work.sh`
+    #[arg(long, group = "mode")]
+    until_low: bool,
+
+    /// Wait until the battery falls to the critical level while
+    /// discharging, and exit
+    #[arg(long, group = "mode")]
+    until_critical: bool,
+
+    /// Show the given notification once, with a made-up level, to check that
+    /// notifications appear, and exit
+    #[arg(long, group = "mode", value_name = "KIND")]
+    test_notify: Option<TestNotification>,
+
+    /// Install a systemd user unit that runs this executable at login, and
+    /// exit
+    #[arg(long, group = "mode")]
+    install: bool,
+
+    /// Remove the systemd user unit written by --install, and exit
+    #[arg(long, group = "mode")]
+    uninstall: bool,
+
+    /// Make the batteries start charging at START and stop at END percent,
+    /// where the driver supports it, and exit; this usually needs root
+    #[arg(long, group = "mode", num_args = 2, value_names = ["START", "END"],
+          value_parser = parse_battery_level)]
+    set_charge_limit: Option<Vec<BatteryLevel>>,
+
+    /// Warn at startup if the battery health is below this percentage; 0
+    /// disables the warning [default: 70]
+    #[arg(long, value_name = "PCT")]
+    health_warn: Option<u8>,
+
+    /// Warn when the battery is hotter than this many degrees Celsius; 0
+    /// disables the warning [default: 45]
+    #[arg(long, value_name = "CELSIUS")]
+    temp_warn: Option<u8>,
+
+    /// Seconds before repeating a temperature warning [default: 600]
+    #[arg(long, value_name = "SECS")]
+    temp_frequency: Option<u64>,
+
+    /// Warn when the battery drains faster than this many percent per minute
+    /// for several readings in a row; 0 disables the warning [default: 0]
+    #[arg(long, value_name = "PCT_PER_MIN")]
+    drain_warn: Option<f32>,
+
+    /// Seconds before repeating a fast drain warning [default: 1800]
+    #[arg(long, value_name = "SECS")]
+    drain_frequency: Option<u64>,
+
+    /// Warn when the batteries gain less than this many watts while
+    /// charging for several readings in a row, e.g. on a weak USB-C charger,
+    /// so 0 only warns when they lose charge; needs batteries that report
+    /// their energy [default: off]
+    #[arg(long, value_name = "WATTS")]
+    slow_charge_warn: Option<f32>,
+
+    /// Seconds before repeating a slow charge warning [default: 1800]
+    #[arg(long, value_name = "SECS")]
+    slow_charge_frequency: Option<u64>,
+
+    /// Seconds after unplugging to hold back low battery warnings, so a brief
+    /// unplug doesn't warn at once, unless the battery is nearly empty
+    /// [default: 0]
+    #[arg(long, value_name = "SECS")]
+    discharge_grace: Option<u64>,
+
+    /// Seconds that SIGUSR1 snoozes alerts for; SIGUSR2 ends the snooze
+    /// early [default: 900]
+    #[arg(long, value_name = "SECS")]
+    snooze: Option<u64>,
+
+    /// Show at most one notification every SECS seconds, whatever it's
+    /// about, holding the rest back until then; critical ones are always
+    /// shown [default: 0, no limit]
+    #[arg(long, value_name = "SECS")]
+    max_notify_rate: Option<u64>,
+
+    /// Hold back the warnings above the critical level, and step
+    /// notifications, between these local times, e.g. 23:00-07:00
+    #[arg(long, value_name = "HH:MM-HH:MM")]
+    quiet_hours: Option<QuietHours>,
+
+    /// Unix socket to answer with the latest reading as JSON, one reply per
+    /// line a client sends, or `profile NAME` to switch profiles [default:
+    /// none]
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// Address to serve Prometheus metrics on at /metrics, e.g.
+    /// 127.0.0.1:9110 [default: none]
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Comma-separated names of the batteries under /sys/class/power_supply,
+    /// or --sysfs-root, to monitor; their charge is combined into a single
+    /// level [default: the batteries found that power the system, skipping
+    /// peripherals', or BAT0 if none are]
+    #[arg(
+        long,
+        visible_alias = "battery",
+        env = "BATTERY_NAME",
+        value_delimiter = ','
+    )]
+    batteries: Option<Vec<String>>,
+
+    /// Directory to find the batteries in instead of /sys/class/power_supply,
+    /// e.g. a fake tree of files for a demo or test
+    #[arg(long, value_name = "DIR")]
+    sysfs_root: Option<PathBuf>,
+
+    /// Where to read the batteries from [default: sysfs]
+    #[arg(long, value_enum)]
+    source: Option<Source>,
+
+    /// What the low and critical warnings are judged by [default: percent]
+    #[arg(long, visible_alias = "threshold-mode", value_enum)]
+    threshold_source: Option<ThresholdSource>,
+
+    /// Minutes of estimated runtime left at or below which to warn that the
+    /// battery is critical, with `--threshold-source minutes` [default: 10]
+    #[arg(long, value_name = "MINUTES")]
+    critical_minutes: Option<u64>,
+
+    /// Where to read the battery level from; `auto` prefers the kernel's
+    /// `capacity` percentage when a single battery exposes it [default: auto]
+    #[arg(long, value_enum)]
+    capacity_source: Option<CapacitySource>,
+
+    /// How to show notifications [default: libnotify, or dbus if built
+    /// without it]
+    #[arg(long, value_enum)]
+    backend: Option<Backend>,
+
+    /// Shell command run in the background for a notification that can't be
+    /// shown, e.g. with no notification server running, with its summary as
+    /// $1 and in BATTERY_SUMMARY and its body as $2 and in BATTERY_BODY.
+    /// For example: swaynag -m "$1: $2", or writing them to a FIFO
+    #[arg(long, value_name = "CMD")]
+    fallback_cmd: Option<String>,
+
+    /// How to tell whether the battery is charging; `ac-online` falls back
+    /// to the battery status when there's no AC adapter [default:
+    /// battery-status]
+    #[arg(long, value_enum)]
+    charge_source: Option<ChargeSource>,
+
+    /// What a battery reporting an `Unknown` status is taken to be doing
+    /// [default: discharging]
+    #[arg(long, value_enum)]
+    unknown_status: Option<UnknownStatus>,
+
+    /// Level in percent at or below which to warn that the battery is
+    /// critical [default: 6%]
+    #[arg(long, value_parser = parse_battery_level)]
+    critical: Option<BatteryLevel>,
+
+    /// Level in percent at or below which to warn that the battery is low
+    /// [default: 15%]
+    #[arg(long, value_parser = parse_battery_level)]
+    low: Option<BatteryLevel>,
+
+    /// Level in percent the battery must rise above before a critical
+    /// warning can fire afresh [default: 5% above --critical]
+    #[arg(long, value_parser = parse_battery_level)]
+    critical_clear: Option<BatteryLevel>,
+
+    /// Level in percent the battery must rise above before a low warning can
+    /// fire afresh [default: 5% above --low]
+    #[arg(long, value_parser = parse_battery_level)]
+    low_clear: Option<BatteryLevel>,
+
+    /// Level in percent at or above which to notify, once per charge, that
+    /// the battery is charged, e.g. 80 to stop charging there [default: 100%]
+    #[arg(long, visible_alias = "charged-level", value_parser = parse_battery_level)]
+    full_level: Option<BatteryLevel>,
+
+    /// Level in percent at or above which to suggest unplugging, once per
+    /// charge, to preserve the battery [default: never]
+    #[arg(long, value_parser = parse_battery_level)]
+    charge_limit_warn: Option<BatteryLevel>,
+
+    /// Notify when the battery starts charging, e.g. to confirm a loose cable
+    /// made contact
+    #[arg(long)]
+    notify_charging: bool,
+
+    /// Notify the battery level once at startup, whatever it is
+    #[arg(long)]
+    notify_on_start: bool,
+
+    /// Notify each time the battery falls to a multiple of this many percent
+    /// while discharging, e.g. at 80%, 70%, 60%… for 10; 0 disables it
+    /// [default: 0]
+    #[arg(long, value_name = "PCT")]
+    step_notify: Option<u8>,
+
+    /// Use the icon theme's battery-level-N icons matching the battery level
+    /// instead of the configured icons
+    #[arg(long)]
+    graded_icons: bool,
+
+    /// Show the energy left in watt-hours in warnings, e.g. "(42.1 Wh)"
+    #[arg(long)]
+    show_energy: bool,
+
+    /// Show the battery level as a progress bar in notifications, for
+    /// notification servers that support the `value` hint
+    #[arg(long)]
+    progress_hint: bool,
+
+    /// Shell command run to play a sound with each critical warning
+    #[arg(long, value_name = "CMD")]
+    critical_sound_cmd: Option<String>,
+
+    /// Play a sound with critical warnings, with --critical-sound-cmd or
+    /// else `canberra-gtk-play --id=battery-caution`
+    #[arg(long, conflicts_with = "no_sound")]
+    sound: bool,
+
+    /// Don't play a sound with critical warnings, even if configured to
+    #[arg(long)]
+    no_sound: bool,
+
+    /// Shell command run in the background with each critical warning, with
+    /// the level as $1 and in BATTERY_LEVEL
+    #[arg(long, value_name = "CMD")]
+    on_critical: Option<String>,
+
+    /// Shell command run in the background with each low warning, with the
+    /// level as $1 and in BATTERY_LEVEL
+    #[arg(long, value_name = "CMD")]
+    on_low: Option<String>,
+
+    /// Level in percent at or below which to suspend the system, once per
+    /// discharge [default: never]
+    #[arg(long, value_parser = parse_battery_level)]
+    suspend_at: Option<BatteryLevel>,
+
+    /// Shell command run to suspend the system [default: systemctl suspend]
+    #[arg(long, value_name = "CMD")]
+    suspend_cmd: Option<String>,
+
+    /// Warn 30 seconds before suspending, giving time to plug in
+    #[arg(long)]
+    suspend_warning: bool,
+
+    /// Hold off idle suspend while a critical warning is showing, until
+    /// plugged in
+    #[arg(long)]
+    inhibit_on_critical: bool,
+
+    /// Seconds to wait between battery readings, instead of polling more
+    /// often as the battery drains [default: 300 above 50%, 60 above 20%,
+    /// otherwise 15]
+    #[arg(long, value_name = "SECS")]
+    poll_interval: Option<u64>,
+
+    /// Exit after this many polls in a row fail to read the battery; 0
+    /// retries forever [default: 10]
+    #[arg(long, value_name = "N")]
+    max_failures: Option<u32>,
+
+    /// Average the level over this many readings, so that a brief jump under
+    /// load doesn't cross a threshold [default: 1]
+    #[arg(long, value_name = "N")]
+    smooth: Option<usize>,
+
+    /// Only believe the battery started or stopped charging once this many
+    /// readings in a row agree, for a loose charger that flaps [default: 1]
+    #[arg(long, value_name = "N")]
+    charge_debounce: Option<u32>,
+
+    /// How long before repeating a critical battery warning, e.g. 90s or 2m,
+    /// unless crit_escalation in the config file shortens it at lower levels
+    /// [default: 60s]
+    #[arg(long, visible_alias = "crit-every", value_name = "DURATION",
+          value_parser = parse_duration)]
+    crit_frequency: Option<Duration>,
+
+    /// How long before repeating a low battery warning [default: 5m]
+    #[arg(long, visible_alias = "low-every", value_name = "DURATION",
+          value_parser = parse_duration)]
+    low_frequency: Option<Duration>,
+
+    /// How long before reminding again that the battery is charged, while it
+    /// stays plugged in; 0 reminds only once [default: 0]
+    #[arg(long, visible_alias = "full-every", value_name = "DURATION",
+          value_parser = parse_duration)]
+    full_frequency: Option<Duration>,
+}
+
+/// Where battery readings come from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// The kernel's power supply class under /sys/class/power_supply
+    #[default]
+    Sysfs,
+    /// UPower's combined display device, read over D-Bus
+    Upower,
+}
+
+/// What warnings are judged by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThresholdSource {
+    /// The level in percent
+    #[default]
+    Percent,
+    /// The firmware's `capacity_level`, with `Low` and `Critical` giving
+    /// those warnings, falling back to the percentage where it's not given
+    CapacityLevel,
+    /// The estimated runtime left for the critical warning, with
+    /// `--critical-minutes`, falling back to the percentage where it's not
+    /// known; the other warnings go by the percentage
+    Minutes,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// libnotify, falling back to dbus for a notification it can't show;
+    /// warnings have no buttons, as libnotify needs a GLib main loop to
+    /// dispatch them
+    #[cfg(feature = "libnotify")]
+    Libnotify,
+    /// The org.freedesktop.Notifications D-Bus interface, without libnotify;
+    /// warnings have buttons to suspend now or dismiss them for 30 minutes
+    Dbus,
+}
+
+impl Default for Backend {
+    fn default() -> Backend {
+        #[cfg(feature = "libnotify")]
+        return Backend::Libnotify;
+        #[cfg(not(feature = "libnotify"))]
+        return Backend::Dbus;
+    }
+}
+
+impl Args {
+    /// Whether logging should be off by default, so as not to garble the
+    /// `--watch` view.
+    pub fn quiet_logs(&self) -> bool {
+        self.watch
+    }
+
+    /// Whether logs should go to stderr, leaving stdout to `--json-events`.
+    pub fn logs_to_stderr(&self) -> bool {
+        self.json_events
+    }
+
+    /// Whether the logs will go to a file, which shouldn't get colours.
+    pub fn logs_to_file(&self) -> bool {
+        self.daemonize
+    }
+
+    /// Uses the config file's `name` profile instead of the one chosen on
+    /// the command line.
+    pub fn with_profile(self, name: Option<String>) -> Args {
+        Args {
+            profile: name.or(self.profile),
+            ..self
+        }
+    }
+}
+
+/// A notification `--test-notify` can show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TestNotification {
+    /// The low battery warning
+    Low,
+    /// The critical battery warning
+    Critical,
+    /// The fully charged notification
+    Full,
+}
+
+/// How urgently a warning asks for attention, as understood by the
+/// notification server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+/// A notification timeout in the config file: milliseconds, `"persistent"`
+/// or `"default"`.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(untagged)]
+enum TimeoutConfig {
+    Millis(u64),
+    Keyword(TimeoutKeyword),
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TimeoutKeyword {
+    Persistent,
+    Default,
+}
+
+impl From<TimeoutConfig> for Timeout {
+    fn from(timeout: TimeoutConfig) -> Timeout {
+        match timeout {
+            TimeoutConfig::Millis(millis) => Timeout::After(Duration::from_millis(millis)),
+            TimeoutConfig::Keyword(TimeoutKeyword::Persistent) => Timeout::Persistent,
+            TimeoutConfig::Keyword(TimeoutKeyword::Default) => Timeout::Default,
+        }
+    }
+}
+
+/// The contents of the TOML config file; every key is optional.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// The profile applied, which is also the one used unless another is
+    /// asked for.
+    profile: Option<String>,
+    battery_name: Option<String>,
+    batteries: Option<Vec<String>>,
+    sysfs_root: Option<PathBuf>,
+    source: Option<Source>,
+    threshold_source: Option<ThresholdSource>,
+    critical_minutes: Option<u64>,
+    capacity_source: Option<CapacitySource>,
+    charge_source: Option<ChargeSource>,
+    unknown_status: Option<UnknownStatus>,
+    backend: Option<Backend>,
+    fallback_cmd: Option<String>,
+    low_level: Option<BatteryLevel>,
+    critical_level: Option<BatteryLevel>,
+    low_clear_level: Option<BatteryLevel>,
+    critical_clear_level: Option<BatteryLevel>,
+    #[serde(alias = "charged_level")]
+    full_level: Option<BatteryLevel>,
+    charge_limit_warn: Option<BatteryLevel>,
+    notify_charging: Option<bool>,
+    notify_on_start: Option<bool>,
+    critical_icon: Option<String>,
+    low_icon: Option<String>,
+    critical_sound: Option<String>,
+    low_sound: Option<String>,
+    critical_urgency: Option<Urgency>,
+    low_urgency: Option<Urgency>,
+    critical_timeout: Option<TimeoutConfig>,
+    low_timeout: Option<TimeoutConfig>,
+    full_icon: Option<String>,
+    graded_icons: Option<bool>,
+    show_energy: Option<bool>,
+    progress_hint: Option<bool>,
+    step_notify: Option<u8>,
+    critical_sound_cmd: Option<String>,
+    sound: Option<bool>,
+    on_critical: Option<String>,
+    on_low: Option<String>,
+    suspend_level: Option<BatteryLevel>,
+    suspend_cmd: Option<String>,
+    suspend_warning: Option<bool>,
+    inhibit_on_critical: Option<bool>,
+    health_warn_level: Option<u8>,
+    temp_warn_celsius: Option<u8>,
+    temp_frequency_secs: Option<u64>,
+    drain_warn_pct_per_min: Option<f32>,
+    drain_frequency_secs: Option<u64>,
+    slow_charge_warn_watts: Option<f32>,
+    slow_charge_frequency_secs: Option<u64>,
+    discharge_grace_secs: Option<u64>,
+    snooze_secs: Option<u64>,
+    max_notify_rate_secs: Option<u64>,
+    quiet_hours: Option<String>,
+    socket_path: Option<PathBuf>,
+    metrics_addr: Option<SocketAddr>,
+    poll_interval_secs: Option<u64>,
+    max_failures: Option<u32>,
+    smooth: Option<usize>,
+    charge_debounce_polls: Option<u32>,
+    adaptive_poll: Option<Vec<AdaptivePollStep>>,
+    low_frequency_secs: Option<u64>,
+    crit_frequency_secs: Option<u64>,
+    full_frequency_secs: Option<u64>,
+    crit_escalation: Option<Vec<EscalationStep>>,
+    tiers: Option<Vec<TierConfig>>,
+    messages: Option<toml::Table>,
+}
+
+/// One `[[adaptive_poll]]` table in the config file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AdaptivePollStep {
+    above: BatteryLevel,
+    interval_secs: u64,
+}
+
+/// One step of a `crit_escalation` or `[[tiers]]` `escalation` array: repeat
+/// the warning every `frequency_secs` once the battery is below `below`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EscalationStep {
+    below: BatteryLevel,
+    frequency_secs: u64,
+}
+
+/// One `[[tiers]]` table in the config file, an extra warning alongside the
+/// low and critical ones.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TierConfig {
+    level: BatteryLevel,
+    clear_level: Option<BatteryLevel>,
+    urgency: Option<Urgency>,
+    timeout: Option<TimeoutConfig>,
+    frequency_secs: Option<u64>,
+    escalation: Option<Vec<EscalationStep>>,
+    summary: Option<String>,
+    message: Option<String>,
+    icon: Option<String>,
+    sound: Option<String>,
+    command: Option<String>,
+}
+
+/// Where `--daemonize` writes its process id and logs.
+#[derive(Debug)]
+pub struct Daemon {
+    pub pidfile: Option<PathBuf>,
+    pub log_file: PathBuf,
+}
+
+/// A level at or below which to warn, such as low or critical.
+#[derive(Debug)]
+pub struct Tier {
+    pub level: BatteryLevel,
+    /// The level the battery must rise above before this warning can fire
+    /// afresh.
+    pub clear: BatteryLevel,
+    pub urgency: Urgency,
+    /// How long the notification stays on screen.
+    pub timeout: Timeout,
+    /// How long before repeating the warning.
+    pub frequency: Duration,
+    /// Shorter frequencies below the given levels, sorted by ascending level,
+    /// so the warning gets more insistent as the battery drains.
+    pub escalation: Vec<(BatteryLevel, Duration)>,
+    pub summary: String,
+    /// The notification body, with `{level}`, `{time_remaining}`, `{watts}`
+    /// and `{health}` standing for what they name.
+    pub message: String,
+    pub icon: String,
+    /// A sound from the freedesktop sound theme, e.g. `battery-caution`, for
+    /// the notification server to play.
+    pub sound: Option<String>,
+    /// Shell commands run in the background with each warning.
+    pub commands: Vec<String>,
+}
+
+impl Tier {
+    /// How long before repeating the warning with the battery at `level`.
+    pub fn frequency_at(&self, level: &BatteryLevel) -> Duration {
+        self.escalation
+            .iter()
+            .find(|(below, _)| level < below)
+            .map_or(self.frequency, |(_, frequency)| *frequency)
+    }
+}
+
+/// How long to wait between battery readings.
+#[derive(Clone, Debug)]
+pub enum PollInterval {
+    Fixed(Duration),
+    /// Intervals sorted by descending level; the first whose level the
+    /// battery is above applies, falling through to the last.
+    Adaptive(Vec<(BatteryLevel, Duration)>),
+}
+
+impl PollInterval {
+    /// The interval to wait after reading `level`, or after a poll where the
+    /// level couldn't be read. It's never under [`MIN_POLL_INTERVAL`], so a
+    /// zero interval can't turn polling into a busy loop.
+    pub fn interval(&self, level: Option<&BatteryLevel>) -> Duration {
+        let interval = match self {
+            PollInterval::Fixed(interval) => *interval,
+            PollInterval::Adaptive(steps) => {
+                let fallback = steps.last().map(|(_, interval)| *interval);
+                level
+                    .and_then(|level| steps.iter().find(|(above, _)| level > above))
+                    .map(|(_, interval)| *interval)
+                    .or(fallback)
+                    .unwrap_or_default()
+            }
+        };
+        interval.max(MIN_POLL_INTERVAL)
+    }
+}
+
+/// What the program was asked to do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Monitor the battery and notify, the default.
+    Monitor,
+    /// Print the battery health and exit.
+    Health,
+    /// Print the battery status as JSON and exit.
+    Status,
+    /// Print the battery cycle count and exit.
+    Cycles,
+    /// Wait until the battery falls to the level while discharging and exit.
+    Until(BatteryLevel),
+    /// Show the warning for the tier at the level once and exit.
+    TestWarning(BatteryLevel),
+    /// Show the fully charged notification once and exit.
+    TestFull,
+    /// Install the systemd user unit and exit.
+    Install,
+    /// Remove the systemd user unit and exit.
+    Uninstall,
+    /// Set the levels charging starts and stops at and exit.
+    SetChargeLimit(BatteryLevel, BatteryLevel),
+    /// Print battery events as JSON lines, warning at the low and critical
+    /// levels.
+    JsonEvents,
+}
+
+/// The effective settings after layering the command line over the config
+/// file over the defaults.
+#[derive(Debug)]
+pub struct Settings {
+    pub mode: Mode,
+    /// Where to detach to with `--daemonize`, if asked to.
+    pub daemon: Option<Daemon>,
+    pub verbose: bool,
+    /// Only log notifications and commands.
+    pub dry_run: bool,
+    /// Whether to draw each poll in the terminal, without notifying.
+    pub watch: bool,
+    /// The config file's profile in use, if any.
+    pub profile: Option<String>,
+    pub source: Source,
+    pub batteries: Vec<String>,
+    /// Where the batteries are listed.
+    pub sysfs_root: PathBuf,
+    pub capacity_source: CapacitySource,
+    pub charge_source: ChargeSource,
+    pub unknown_status: UnknownStatus,
+    pub backend: Backend,
+    /// Run for a notification that can't be shown, if given.
+    pub fallback_cmd: Option<String>,
+    pub threshold_source: ThresholdSource,
+    /// The runtime left that the critical warning stands for with
+    /// `--threshold-source minutes`.
+    pub critical_minutes: Duration,
+    /// The levels of the low and critical warnings, which the firmware's
+    /// `Low` and `Critical` stand for with `--threshold-source
+    /// capacity-level`.
+    pub low_level: BatteryLevel,
+    pub critical_level: BatteryLevel,
+    /// The warnings sorted by ascending level, so the most severe comes
+    /// first.
+    pub tiers: Vec<Tier>,
+    pub full_level: BatteryLevel,
+    pub charge_limit_warn: Option<BatteryLevel>,
+    /// Whether to notify when charging starts.
+    pub notify_charging: bool,
+    /// Whether to notify the battery level at startup.
+    pub notify_on_start: bool,
+    pub full_icon: String,
+    /// How long before reminding again that the battery is charged, if at
+    /// all.
+    pub full_frequency: Option<Duration>,
+    /// Whether to pick icons by battery level instead of the configured ones.
+    pub graded_icons: bool,
+    /// Whether warnings show the energy left.
+    pub show_energy: bool,
+    /// Whether to send the level as the `value` hint.
+    pub progress_hint: bool,
+    /// The step in percent to notify at while discharging, if any.
+    pub step_notify: Option<u8>,
+    pub suspend_at: Option<BatteryLevel>,
+    pub suspend_cmd: String,
+    pub suspend_warning: bool,
+    /// Whether to block idle suspend while a critical warning is showing.
+    pub inhibit_on_critical: bool,
+    /// Health percentage below which to warn at startup, if any.
+    pub health_warn: Option<f32>,
+    /// Temperature in degrees Celsius above which to warn, if any.
+    pub temp_warn: Option<f32>,
+    pub temp_frequency: Duration,
+    /// Drain in percent per minute above which to warn, if any.
+    pub drain_warn: Option<f32>,
+    pub drain_frequency: Duration,
+    /// Net charging power in watts below which to warn, if at all.
+    pub slow_charge_warn: Option<f32>,
+    pub slow_charge_frequency: Duration,
+    /// How long after unplugging to hold back warnings, if at all.
+    pub discharge_grace: Option<Duration>,
+    /// How long SIGUSR1 snoozes alerts for.
+    pub snooze: Duration,
+    /// The least time between notifications other than critical ones, if
+    /// limited.
+    pub max_notify_rate: Option<Duration>,
+    /// When only the critical warning and any more severe are shown.
+    pub quiet_hours: Option<QuietHours>,
+    /// Where to serve the latest reading, if anywhere.
+    pub socket: Option<PathBuf>,
+    /// Where to serve metrics, if anywhere.
+    pub metrics_addr: Option<SocketAddr>,
+    pub poll_interval: PollInterval,
+    /// Consecutive failed polls after which to give up, if any.
+    pub max_failures: Option<u32>,
+    /// How many readings the level is averaged over.
+    pub smooth: usize,
+    /// How many readings in a row a change in charging must last for.
+    pub charge_debounce: u32,
+    /// The text of the notifications.
+    pub messages: Messages,
+}
+
+/// The steps of an escalation array in the config file, sorted by ascending
+/// level.
+fn escalation(steps: Option<Vec<EscalationStep>>) -> Vec<(BatteryLevel, Duration)> {
+    let mut steps: Vec<_> = steps
+        .unwrap_or_default()
+        .into_iter()
+        .map(|step| (step.below, Duration::from_secs(step.frequency_secs)))
+        .collect();
+    steps.sort_by(|(a, _), (b, _)| a.cmp(b));
+    steps
+}
+
+/// A duration in seconds, or with units such as `90s`, `5m` or `1h30m`.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    if let Ok(secs) = s.parse() {
+        return Ok(Duration::from_secs(secs));
+    }
+    if s.is_empty() {
+        return Err("A duration can't be empty".to_string());
+    }
+    let mut secs = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let count: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| format!("{s:?} is not a duration, such as 90s or 5m"))?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            unit => {
+                return Err(format!(
+                    "Unknown unit {unit:?} in {s:?}, expected s, m or h"
+                ));
+            }
+        };
+        rest = &rest[unit_len..];
+        secs += count * unit;
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+fn parse_battery_level(s: &str) -> Result<BatteryLevel, String> {
+    let percent: i64 = s
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|e| format!("{s:?} is not a percentage: {e}"))?;
+    BatteryLevel::try_new(percent).map_err(|e| e.to_string())
+}
+
+/// `$XDG_CONFIG_HOME`, or `~/.config` if that isn't set.
+pub fn config_home() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+/// `$XDG_STATE_HOME`, or `~/.local/state` if that isn't set.
+fn state_home() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    Some(config_home()?.join(APP_NAME).join("config.toml"))
+}
+
+impl Config {
+    /// Reads the config file at `path`, or the default location. A missing
+    /// file yields an empty config, but one that fails to parse is an error.
+    ///
+    /// The keys of the `profile` table under `[profiles]`, or of the one the
+    /// file's own `profile` key names, replace the top-level ones.
+    fn load(path: Option<PathBuf>, profile: Option<&str>) -> Result<Config, anyhow::Error> {
+        let missing = || match profile {
+            Some(name) => bail!("No profile {name}, as there is no config file"),
+            None => Ok(Config::default()),
+        };
+        let Some(path) = path.or_else(default_config_path) else {
+            return missing();
+        };
+        let raw = match read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == ErrorKind::NotFound => return missing(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        let invalid = || format!("Invalid config file {}", path.display());
+        let mut table: Table = toml::from_str(&raw).with_context(invalid)?;
+        apply_profile(&mut table, profile).with_context(invalid)?;
+        Value::Table(table).try_into().with_context(invalid)
+    }
+}
+
+/// Replaces the top-level keys in `table` with those of the chosen profile,
+/// if any, and drops the rest of `[profiles]`. Keys are replaced whole, so a
+/// profile's `tiers` replace all of the top-level ones.
+fn apply_profile(table: &mut Table, profile: Option<&str>) -> Result<(), anyhow::Error> {
+    let mut profiles = match table.remove("profiles") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => bail!("profiles must be a table of profiles"),
+        None => Table::new(),
+    };
+    let name = match (profile, table.get("profile")) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(Value::String(name))) => name.clone(),
+        (None, Some(_)) => bail!("profile must be the name of a profile"),
+        (None, None) => return Ok(()),
+    };
+    match profiles.remove(&name) {
+        Some(Value::Table(overrides)) => table.extend(overrides),
+        Some(_) => bail!("profiles.{name} must be a table"),
+        None => bail!("No profile {name} under [profiles]"),
+    }
+    table.insert("profile".to_string(), Value::String(name));
+    Ok(())
+}
+
+impl Settings {
+    /// Resolves the settings from the command line and config file.
+    pub fn load(args: Args) -> Result<Settings, anyhow::Error> {
+        let config = Config::load(args.config, args.profile.as_deref())?;
+
+        let critical = args
+            .critical
+            .or(config.critical_level)
+            .unwrap_or(CRITICAL_BATTERY_LEVEL);
+        let low = args.low.or(config.low_level).unwrap_or(LOW_BATTERY_LEVEL);
+        let clear_level = |threshold: &BatteryLevel| {
+            BatteryLevel::new((threshold.level() + CLEAR_MARGIN).min(100))
+        };
+        if critical >= low {
+            bail!("The critical level ({critical}) must be below the low level ({low})");
+        }
+        let mode = if args.health {
+            Mode::Health
+        } else if args.status {
+            Mode::Status
+        } else if args.cycles {
+            Mode::Cycles
+        } else if args.until_low {
+            Mode::Until(low.clone())
+        } else if args.until_critical {
+            Mode::Until(critical.clone())
+        } else if let Some(test) = args.test_notify {
+            match test {
+                TestNotification::Low => Mode::TestWarning(low.clone()),
+                TestNotification::Critical => Mode::TestWarning(critical.clone()),
+                TestNotification::Full => Mode::TestFull,
+            }
+        } else if args.install {
+            Mode::Install
+        } else if args.uninstall {
+            Mode::Uninstall
+        } else if let Some([start, end]) = args.set_charge_limit.as_deref() {
+            if start >= end {
+                bail!("The charging start ({start}) must be below where it stops ({end})");
+            }
+            Mode::SetChargeLimit(start.clone(), end.clone())
+        } else if args.json_events {
+            Mode::JsonEvents
+        } else {
+            Mode::Monitor
+        };
+
+        let sound = !args.no_sound && config.sound != Some(false);
+        // Only played if asked for, as the default needs canberra-gtk-play.
+        let critical_sound_cmd = args
+            .critical_sound_cmd
+            .or(config.critical_sound_cmd)
+            .or_else(|| {
+                (args.sound || config.sound == Some(true)).then(|| CRITICAL_SOUND_CMD.to_string())
+            })
+            .filter(|_| sound);
+        let messages = messages::load(config.messages)?;
+        let mut tiers = vec![
+            Tier {
+                clear: args
+                    .critical_clear
+                    .or(config.critical_clear_level)
+                    .unwrap_or_else(|| clear_level(&critical)),
+                level: critical.clone(),
+                urgency: config.critical_urgency.unwrap_or(Urgency::Critical),
+                timeout: config
+                    .critical_timeout
+                    .map_or(Timeout::Persistent, Timeout::from),
+                frequency: args
+                    .crit_frequency
+                    .or(config.crit_frequency_secs.map(Duration::from_secs))
+                    .unwrap_or(CRIT_FREQUENCY),
+                escalation: escalation(config.crit_escalation),
+                summary: messages.critical_summary.clone(),
+                message: messages.critical_message.clone(),
+                icon: config
+                    .critical_icon
+                    .unwrap_or_else(|| CRITICAL_ICON.to_string()),
+                sound: config.critical_sound.filter(|_| sound),
+                commands: critical_sound_cmd
+                    .into_iter()
+                    .chain(args.on_critical.or(config.on_critical))
+                    .collect(),
+            },
+            Tier {
+                clear: args
+                    .low_clear
+                    .or(config.low_clear_level)
+                    .unwrap_or_else(|| clear_level(&low)),
+                level: low.clone(),
+                urgency: config.low_urgency.unwrap_or(Urgency::Normal),
+                timeout: config.low_timeout.map_or(LOW_TIMEOUT, Timeout::from),
+                frequency: args
+                    .low_frequency
+                    .or(config.low_frequency_secs.map(Duration::from_secs))
+                    .unwrap_or(LOW_FREQUENCY),
+                escalation: Vec::new(),
+                summary: messages.low_summary.clone(),
+                message: messages.low_message.clone(),
+                icon: config.low_icon.unwrap_or_else(|| LOW_ICON.to_string()),
+                sound: config.low_sound.filter(|_| sound),
+                commands: args.on_low.or(config.on_low).into_iter().collect(),
+            },
+        ];
+        for tier in config.tiers.unwrap_or_default() {
+            tiers.push(Tier {
+                clear: tier.clear_level.unwrap_or_else(|| clear_level(&tier.level)),
+                level: tier.level,
+                urgency: tier.urgency.unwrap_or(Urgency::Normal),
+                timeout: tier.timeout.map_or(Timeout::Default, Timeout::from),
+                frequency: tier
+                    .frequency_secs
+                    .map(Duration::from_secs)
+                    .unwrap_or(LOW_FREQUENCY),
+                escalation: escalation(tier.escalation),
+                summary: tier
+                    .summary
+                    .unwrap_or_else(|| messages.tier_summary.clone()),
+                message: tier
+                    .message
+                    .unwrap_or_else(|| messages.tier_message.clone()),
+                icon: tier.icon.unwrap_or_else(|| LOW_ICON.to_string()),
+                sound: tier.sound.filter(|_| sound),
+                commands: tier.command.into_iter().collect(),
+            });
+        }
+        tiers.sort_by(|a, b| a.level.cmp(&b.level));
+        for tier in &tiers {
+            if let Some(name) =
+                messages::unknown_placeholder(&tier.message, &messages::WARNING_PLACEHOLDERS)
+            {
+                bail!(
+                    "Unknown placeholder {{{name}}} in the message for the {} warning",
+                    tier.level
+                );
+            }
+        }
+
+        let daemon = if args.daemonize {
+            if mode != Mode::Monitor {
+                bail!("--daemonize only applies while monitoring");
+            }
+            let log_file = match args.log_file {
+                Some(path) => path,
+                None => state_home()
+                    .ok_or_else(|| anyhow!("No --log-file given and HOME isn't set"))?
+                    .join(APP_NAME)
+                    .join(format!("{APP_NAME}.log")),
+            };
+            Some(Daemon {
+                pidfile: args.pidfile,
+                log_file,
+            })
+        } else {
+            None
+        };
+        let sysfs_root = args
+            .sysfs_root
+            .or(config.sysfs_root)
+            .unwrap_or_else(|| PathBuf::from(POWER_SUPPLY_DIR));
+
+        let settings = Settings {
+            mode,
+            daemon,
+            verbose: args.verbose,
+            dry_run: args.dry_run,
+            watch: args.watch,
+            profile: config.profile,
+            source: args.source.or(config.source).unwrap_or_default(),
+            batteries: args
+                .batteries
+                .or(config.batteries)
+                .or(config.battery_name.map(|name| vec![name]))
+                .unwrap_or_else(|| {
+                    Some(system_batteries(&sysfs_root))
+                        .filter(|found| !found.is_empty())
+                        .unwrap_or_else(|| vec![DEFAULT_BATTERY.to_string()])
+                }),
+            sysfs_root,
+            capacity_source: args
+                .capacity_source
+                .or(config.capacity_source)
+                .unwrap_or(CapacitySource::Auto),
+            charge_source: args
+                .charge_source
+                .or(config.charge_source)
+                .unwrap_or(ChargeSource::BatteryStatus),
+            unknown_status: args
+                .unknown_status
+                .or(config.unknown_status)
+                .unwrap_or_default(),
+            backend: args.backend.or(config.backend).unwrap_or_default(),
+            fallback_cmd: args.fallback_cmd.or(config.fallback_cmd),
+            threshold_source: args
+                .threshold_source
+                .or(config.threshold_source)
+                .unwrap_or_default(),
+            critical_minutes: match args
+                .critical_minutes
+                .or(config.critical_minutes)
+                .unwrap_or(CRITICAL_MINUTES)
+            {
+                0 => bail!("critical_minutes must be at least 1"),
+                minutes => Duration::from_secs(minutes * 60),
+            },
+            low_level: low,
+            critical_level: critical,
+            tiers,
+            charge_limit_warn: args.charge_limit_warn.or(config.charge_limit_warn),
+            notify_charging: args.notify_charging || config.notify_charging.unwrap_or(false),
+            notify_on_start: args.notify_on_start || config.notify_on_start.unwrap_or(false),
+            full_level: args
+                .full_level
+                .or(config.full_level)
+                .unwrap_or(FULL_BATTERY_LEVEL),
+            full_icon: config.full_icon.unwrap_or_else(|| FULL_ICON.to_string()),
+            full_frequency: args
+                .full_frequency
+                .or(config.full_frequency_secs.map(Duration::from_secs))
+                .filter(|frequency| !frequency.is_zero()),
+            graded_icons: args.graded_icons || config.graded_icons.unwrap_or(false),
+            show_energy: args.show_energy || config.show_energy.unwrap_or(false),
+            progress_hint: args.progress_hint || config.progress_hint.unwrap_or(false),
+            step_notify: match args.step_notify.or(config.step_notify).unwrap_or(0) {
+                0 => None,
+                step => Some(step),
+            },
+            suspend_at: args.suspend_at.or(config.suspend_level),
+            suspend_cmd: args
+                .suspend_cmd
+                .or(config.suspend_cmd)
+                .unwrap_or_else(|| SUSPEND_CMD.to_string()),
+            suspend_warning: args.suspend_warning || config.suspend_warning.unwrap_or(false),
+            inhibit_on_critical: args.inhibit_on_critical
+                || config.inhibit_on_critical.unwrap_or(false),
+            health_warn: match args
+                .health_warn
+                .or(config.health_warn_level)
+                .unwrap_or(HEALTH_WARN_LEVEL)
+            {
+                0 => None,
+                percent => Some(percent as f32),
+            },
+            temp_warn: match args
+                .temp_warn
+                .or(config.temp_warn_celsius)
+                .unwrap_or(TEMP_WARN_CELSIUS)
+            {
+                0 => None,
+                celsius => Some(celsius as f32),
+            },
+            temp_frequency: args
+                .temp_frequency
+                .or(config.temp_frequency_secs)
+                .map(Duration::from_secs)
+                .unwrap_or(TEMP_FREQUENCY),
+            drain_warn: args
+                .drain_warn
+                .or(config.drain_warn_pct_per_min)
+                .filter(|rate| *rate > 0.0),
+            drain_frequency: args
+                .drain_frequency
+                .or(config.drain_frequency_secs)
+                .map(Duration::from_secs)
+                .unwrap_or(DRAIN_FREQUENCY),
+            slow_charge_warn: args.slow_charge_warn.or(config.slow_charge_warn_watts),
+            slow_charge_frequency: args
+                .slow_charge_frequency
+                .or(config.slow_charge_frequency_secs)
+                .map(Duration::from_secs)
+                .unwrap_or(SLOW_CHARGE_FREQUENCY),
+            discharge_grace: args
+                .discharge_grace
+                .or(config.discharge_grace_secs)
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            max_notify_rate: args
+                .max_notify_rate
+                .or(config.max_notify_rate_secs)
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            snooze: args
+                .snooze
+                .or(config.snooze_secs)
+                .map(Duration::from_secs)
+                .unwrap_or(SNOOZE),
+            quiet_hours: match args.quiet_hours {
+                Some(window) => Some(window),
+                None => config
+                    .quiet_hours
+                    .map(|window| window.parse().context("Invalid quiet_hours"))
+                    .transpose()?,
+            },
+            socket: args.socket.or(config.socket_path),
+            metrics_addr: args.metrics_addr.or(config.metrics_addr),
+            poll_interval: match args.poll_interval.or(config.poll_interval_secs) {
+                Some(secs) => PollInterval::Fixed(Duration::from_secs(secs)),
+                None => {
+                    let mut steps = match config.adaptive_poll {
+                        Some(steps) if steps.is_empty() => bail!("adaptive_poll is empty"),
+                        Some(steps) => steps
+                            .into_iter()
+                            .map(|step| (step.above, step.interval_secs))
+                            .collect(),
+                        None => ADAPTIVE_POLL.to_vec(),
+                    }
+                    .into_iter()
+                    .map(|(above, secs)| (above, Duration::from_secs(secs)))
+                    .collect::<Vec<_>>();
+                    steps.sort_by(|(a, _), (b, _)| b.cmp(a));
+                    PollInterval::Adaptive(steps)
+                }
+            },
+            max_failures: match args
+                .max_failures
+                .or(config.max_failures)
+                .unwrap_or(MAX_FAILURES)
+            {
+                0 => None,
+                max => Some(max),
+            },
+            smooth: match args.smooth.or(config.smooth).unwrap_or(1) {
+                0 => bail!("smooth must be at least 1"),
+                window => window,
+            },
+            charge_debounce: match args
+                .charge_debounce
+                .or(config.charge_debounce_polls)
+                .unwrap_or(1)
+            {
+                0 => bail!("charge_debounce_polls must be at least 1"),
+                polls => polls,
+            },
+            messages,
+        };
+
+        if let Some(pair) = settings
+            .tiers
+            .windows(2)
+            .find(|pair| pair[0].level == pair[1].level)
+        {
+            bail!("Two warnings share the level {}", pair[0].level);
+        }
+        let poll_intervals = match &settings.poll_interval {
+            PollInterval::Fixed(interval) => vec![*interval],
+            PollInterval::Adaptive(steps) => steps.iter().map(|(_, interval)| *interval).collect(),
+        };
+        if poll_intervals
+            .iter()
+            .any(|interval| *interval < MIN_POLL_INTERVAL)
+        {
+            bail!(
+                "The poll interval must be at least {} second",
+                MIN_POLL_INTERVAL.as_secs()
+            );
+        }
+        if settings.tiers.iter().any(|tier| {
+            tier.frequency.is_zero()
+                || tier
+                    .escalation
+                    .iter()
+                    .any(|(_, frequency)| frequency.is_zero())
+        }) {
+            bail!("A warning's frequency must be at least 1 second");
+        }
+        if settings.tiers.iter().any(|tier| tier.clear < tier.level) {
+            bail!("A clear level cannot be below the level it clears");
+        }
+        if let Some(highest) = settings.tiers.last()
+            && highest.level >= settings.full_level
+        {
+            bail!(
+                "The warning level {} must be below the full level ({})",
+                highest.level,
+                settings.full_level
+            );
+        }
+        if let Some(warning) = settings.critical_repeat_warning() {
+            warn!("{warning}");
+        }
+        Ok(settings)
+    }
+
+    /// Why the critical warning won't repeat as often as asked, if it won't.
+    /// Readings between polls go unseen, so it repeats no sooner than the
+    /// next poll at its level.
+    fn critical_repeat_warning(&self) -> Option<String> {
+        let critical = self
+            .tiers
+            .iter()
+            .find(|tier| tier.level == self.critical_level)?;
+        let poll = self.poll_interval.interval(Some(&critical.level));
+        (critical.frequency < poll).then(|| {
+            format!(
+                "The critical warning repeats every {}s, but the battery is only polled every \
+                 {}s at {}, so it will repeat every {}s",
+                critical.frequency.as_secs(),
+                poll.as_secs(),
+                critical.level,
+                poll.as_secs()
+            )
+        })
+    }
+
+    /// Describes each setting that differs in `new`, for logging a reload.
+    /// Settings only read at startup are marked as needing a restart.
+    pub fn changes(&self, new: &Settings) -> Vec<String> {
+        let mut changes = Vec::new();
+        macro_rules! compare {
+            ($note:expr; $($field:ident),*) => {
+                $(
+                    let before = format!("{:?}", self.$field);
+                    let after = format!("{:?}", new.$field);
+                    if before != after {
+                        changes.push(format!("{}: {before} -> {after}{}", stringify!($field), $note));
+                    }
+                )*
+            };
+        }
+        compare!("";
+            profile, threshold_source, critical_minutes, low_level, critical_level, tiers,
+            full_level, charge_limit_warn, notify_charging, full_icon, full_frequency,
+            show_energy, step_notify, suspend_at, suspend_cmd, suspend_warning,
+            inhibit_on_critical, temp_warn, temp_frequency, drain_warn, drain_frequency,
+            slow_charge_warn, slow_charge_frequency, discharge_grace, quiet_hours, poll_interval,
+            max_failures, charge_debounce);
+        compare!(" (after a restart)";
+            daemon, source, batteries, sysfs_root, capacity_source, charge_source,
+            unknown_status, backend, fallback_cmd, notify_on_start, graded_icons, progress_hint,
+            health_warn, snooze, max_notify_rate, socket, metrics_addr, smooth, messages);
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(raw)
+    }
+
+    #[test]
+    fn levels() {
+        let config =
+            parse("low_level = 20\ncritical_level = \"5%\"\nfull_level = 100\nsuspend_level = 0")
+                .unwrap();
+        assert_eq!(config.low_level, Some(BatteryLevel::saturating(20)));
+        assert_eq!(config.critical_level, Some(BatteryLevel::saturating(5)));
+        assert_eq!(config.full_level, Some(BatteryLevel::saturating(100)));
+        assert_eq!(config.suspend_level, Some(BatteryLevel::saturating(0)));
+    }
+
+    #[test]
+    fn level_over_100() {
+        let e = parse("low_level = 101").err().unwrap();
+        assert!(e.to_string().contains("101 is not a level"), "{e}");
+        assert!(parse("[[tiers]]\nlevel = 101").is_err());
+        assert!(parse("[[adaptive_poll]]\nabove = 101\ninterval_secs = 60").is_err());
+    }
+
+    /// The settings for `args`, without a config file.
+    fn settings(args: &[&str]) -> Settings {
+        let args = ["battery-notifier", "--config", "/nonexistent/config.toml"]
+            .into_iter()
+            .chain(args.iter().copied());
+        Settings::load(Args::try_parse_from(args).unwrap()).unwrap()
+    }
+
+    /// The commands run with a critical warning for `args`.
+    fn critical_commands(args: &[&str]) -> Vec<String> {
+        let settings = settings(args);
+        let critical = settings
+            .tiers
+            .iter()
+            .find(|tier| tier.urgency == Urgency::Critical)
+            .unwrap();
+        critical.commands.clone()
+    }
+
+    #[test]
+    fn critical_sound_is_opt_in() {
+        assert!(critical_commands(&[]).is_empty());
+        assert_eq!(critical_commands(&["--sound"]), [CRITICAL_SOUND_CMD]);
+        assert_eq!(
+            critical_commands(&["--critical-sound-cmd", "paplay alarm.oga"]),
+            ["paplay alarm.oga"]
+        );
+        assert!(
+            critical_commands(&["--critical-sound-cmd", "paplay alarm.oga", "--no-sound"])
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn durations() {
+        let cases = [
+            ("90", 90),
+            ("90s", 90),
+            ("5m", 5 * 60),
+            ("2h", 2 * 60 * 60),
+            ("1h30m", 90 * 60),
+            ("1m30s", 90),
+            ("0s", 0),
+        ];
+        for (raw, secs) in cases {
+            assert_eq!(
+                parse_duration(raw),
+                Ok(Duration::from_secs(secs)),
+                "{raw:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn bad_durations() {
+        for raw in ["", "m", "5d", "5 m", "-5s", "1.5h", "5ms"] {
+            assert!(parse_duration(raw).is_err(), "{raw:?}");
+        }
+        let e = parse_duration("5d").unwrap_err();
+        assert!(e.contains("Unknown unit \"d\""), "{e}");
+    }
+
+    #[test]
+    fn critical_repeating_faster_than_polls_is_warned_of() {
+        let warning = settings(&["--poll-interval", "120", "--crit-every", "60"])
+            .critical_repeat_warning()
+            .unwrap();
+        assert!(warning.contains("repeats every 60s"), "{warning}");
+        assert!(warning.contains("polled every 120s"), "{warning}");
+        let settings = settings(&["--poll-interval", "60", "--crit-every", "60"]);
+        assert_eq!(settings.critical_repeat_warning(), None);
+    }
+
+    #[test]
+    fn negative_level() {
+        let e = parse("critical_level = -1").err().unwrap();
+        assert!(e.to_string().contains("-1 is not a level"), "{e}");
+        assert!(parse("[[crit_escalation]]\nbelow = -5\nfrequency_secs = 60").is_err());
+    }
+}
@@ -0,0 +1,61 @@
+//! `--daemonize`: detaching from the terminal into the background, for when
+//! the daemon is started from a shell rather than by systemd.
+
+use std::fs::{File, OpenOptions, create_dir_all, write};
+use std::io;
+use std::os::fd::AsRawFd;
+
+use anyhow::Context;
+
+use crate::config::Daemon;
+
+/// Forks twice, so the process carrying on is in a session of its own and
+/// can never regain a terminal, with stdin from `/dev/null` and stdout and
+/// stderr appended to the log file. The original process exits.
+///
+/// This must run before the tokio runtime starts, as only the thread that
+/// forks carries on in the child. The working directory is kept, so relative
+/// paths given on the command line still work.
+pub fn daemonize(daemon: &Daemon) -> Result<(), anyhow::Error> {
+    // Opened first, so that a bad path is reported on the terminal.
+    if let Some(dir) = daemon.log_file.parent() {
+        create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&daemon.log_file)
+        .with_context(|| format!("Failed to open {}", daemon.log_file.display()))?;
+    let null = File::open("/dev/null").context("Failed to open /dev/null")?;
+
+    fork().context("Failed to fork")?;
+    // SAFETY: `setsid` and `dup2` take no pointers, and the descriptors are
+    // open for the duration.
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error()).context("Failed to start a session");
+    }
+    fork().context("Failed to fork")?;
+    for (from, to) in [(&null, 0), (&log, 1), (&log, 2)] {
+        if unsafe { libc::dup2(from.as_raw_fd(), to) } == -1 {
+            return Err(io::Error::last_os_error()).context("Failed to redirect stdio");
+        }
+    }
+
+    if let Some(pidfile) = &daemon.pidfile {
+        write(pidfile, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Failed to write {}", pidfile.display()))?;
+    }
+    Ok(())
+}
+
+/// Forks, returning in the child while the parent exits at once, without
+/// running destructors that belong to the child now.
+fn fork() -> Result<(), io::Error> {
+    // SAFETY: no other threads are running yet, so the child can't inherit a
+    // lock held by one, and `_exit` is safe to call after forking.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
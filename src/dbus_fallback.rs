@@ -0,0 +1,188 @@
+//! One notifier falling back to another for a notification it can't show,
+//! as libnotify does to the D-Bus backend, e.g. while it can't be
+//! initialized, before `--fallback-cmd`.
+
+use tracing::warn;
+
+#[cfg(feature = "libnotify")]
+use crate::command_fallback::CommandFallback;
+use crate::notification::{Action, Message, Notifier};
+
+/// `notifier`, falling back to `dbus` for a notification it fails to show and
+/// then to `command`, in that order.
+#[cfg(feature = "libnotify")]
+pub fn with_fallbacks<A: Notifier, B: Notifier>(
+    notifier: A,
+    dbus: B,
+    command: Option<String>,
+) -> CommandFallback<Fallback<A, B>> {
+    CommandFallback::new(Fallback::new(notifier, dbus), command)
+}
+
+/// Shows notifications through one notifier, or through another for those the
+/// first fails to show. Only libnotify falls back like this, but it's built
+/// without it too so its tests run there.
+#[cfg_attr(not(feature = "libnotify"), allow(dead_code))]
+pub struct Fallback<A, B> {
+    first: A,
+    second: B,
+}
+
+#[cfg_attr(not(feature = "libnotify"), allow(dead_code))]
+impl<A: Notifier, B: Notifier> Fallback<A, B> {
+    pub fn new(first: A, second: B) -> Fallback<A, B> {
+        Fallback { first, second }
+    }
+}
+
+/// Which of a [`Fallback`]'s notifiers showed a notification.
+pub enum FallbackHandle<A, B> {
+    First(A),
+    Second(B),
+}
+
+impl<A: Notifier, B: Notifier> Notifier for Fallback<A, B> {
+    type Handle = FallbackHandle<A::Handle, B::Handle>;
+
+    /// A notification shown by the other notifier isn't replaced, but shown
+    /// afresh.
+    async fn show(
+        &self,
+        message: &Message,
+        replaces: Option<&Self::Handle>,
+    ) -> Result<Self::Handle, anyhow::Error> {
+        let first = match replaces {
+            Some(FallbackHandle::First(handle)) => Some(handle),
+            _ => None,
+        };
+        match self.first.show(message, first).await {
+            Ok(handle) => Ok(FallbackHandle::First(handle)),
+            Err(e) => {
+                warn!("Failed to notify: {e:#}, falling back");
+                let second = match replaces {
+                    Some(FallbackHandle::Second(handle)) => Some(handle),
+                    _ => None,
+                };
+                let handle = self.second.show(message, second).await?;
+                Ok(FallbackHandle::Second(handle))
+            }
+        }
+    }
+
+    async fn close(&self, handle: &Self::Handle) -> Result<(), anyhow::Error> {
+        match handle {
+            FallbackHandle::First(handle) => self.first.close(handle).await,
+            FallbackHandle::Second(handle) => self.second.close(handle).await,
+        }
+    }
+
+    async fn action(&self) -> Action {
+        tokio::select! {
+            action = self.first.action() => action,
+            action = self.second.action() => action,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use anyhow::bail;
+
+    use super::*;
+    use crate::command_fallback::CommandFallback;
+    use crate::config::Urgency;
+    use crate::notification::Timeout;
+
+    /// The names of the stubs that showed each notification, in order.
+    type Shown = Rc<RefCell<Vec<&'static str>>>;
+
+    /// A notifier that either shows everything or fails to show anything.
+    struct Stub {
+        name: &'static str,
+        works: bool,
+        shown: Shown,
+    }
+
+    impl Notifier for Stub {
+        type Handle = &'static str;
+
+        async fn show(
+            &self,
+            _message: &Message,
+            _replaces: Option<&&'static str>,
+        ) -> Result<&'static str, anyhow::Error> {
+            if !self.works {
+                bail!("{} is down", self.name);
+            }
+            self.shown.borrow_mut().push(self.name);
+            Ok(self.name)
+        }
+
+        async fn close(&self, _handle: &&'static str) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+    }
+
+    fn message() -> Message {
+        Message {
+            summary: "Battery Low".to_string(),
+            body: "Battery at 14%".to_string(),
+            icon: "battery-low".to_string(),
+            urgency: Urgency::Normal,
+            timeout: Timeout::Default,
+            value: None,
+            sound: None,
+            actions: Vec::new(),
+        }
+    }
+
+    /// Shows a message through libnotify and D-Bus stubs working as given,
+    /// returning which stub showed it; with neither, `command` is run.
+    async fn show(libnotify: bool, dbus: bool, command: Option<String>) -> Vec<&'static str> {
+        let shown = Shown::default();
+        let stub = |name, works| Stub {
+            name,
+            works,
+            shown: shown.clone(),
+        };
+        let notifier = CommandFallback::new(
+            Fallback::new(stub("libnotify", libnotify), stub("dbus", dbus)),
+            command,
+        );
+        notifier.show(&message(), None).await.unwrap();
+        shown.take()
+    }
+
+    #[tokio::test]
+    async fn libnotify_first() {
+        assert_eq!(show(true, true, None).await, ["libnotify"]);
+    }
+
+    #[tokio::test]
+    async fn dbus_once_libnotify_fails() {
+        assert_eq!(show(false, true, None).await, ["dbus"]);
+    }
+
+    #[tokio::test]
+    async fn command_once_both_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("shown");
+        let command = format!("printf '%s: %s' \"$1\" \"$2\" > '{}'", out.display());
+        assert!(show(false, false, Some(command)).await.is_empty());
+        // The command runs in the background.
+        for _ in 0..100 {
+            if let Ok(written) = std::fs::read_to_string(&out)
+                && !written.is_empty()
+            {
+                assert_eq!(written, "Battery Low: Battery at 14%");
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("The fallback command didn't run");
+    }
+}
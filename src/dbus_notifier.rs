@@ -0,0 +1,227 @@
+//! Notifications sent straight to the `org.freedesktop.Notifications` D-Bus
+//! interface with zbus, for systems without libnotify.
+//!
+//! A notification's actions are heard of from the server's `ActionInvoked`
+//! signals on the same connection, so no GLib main loop is needed to dispatch
+//! them.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use futures::StreamExt;
+use tokio::sync::{Mutex, mpsc};
+use tokio::task::JoinHandle;
+use tracing::warn;
+use zbus::zvariant::Value;
+
+use crate::config::Urgency;
+use crate::notification::{Action, Message, Notifier, Timeout};
+
+#[zbus::proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+
+    fn close_notification(&self, id: u32) -> zbus::Result<()>;
+
+    fn get_server_information(&self) -> zbus::Result<(String, String, String, String)>;
+
+    #[zbus(signal)]
+    fn action_invoked(&self, id: u32, action_key: String) -> zbus::Result<()>;
+}
+
+/// A notification's id and the key of the action picked on it.
+type Invoked = (u32, String);
+
+pub struct DbusNotifier {
+    app_name: String,
+    /// The connection to the notification server, made on first use and
+    /// again after a call on it fails.
+    proxy: RefCell<Option<NotificationsProxy<'static>>>,
+    /// The notifications shown with actions, by id.
+    with_actions: RefCell<HashSet<u32>>,
+    /// Forwards the `ActionInvoked` signals, from the first notification
+    /// shown with actions, and again once the connection it listened on is
+    /// gone.
+    listener: RefCell<Option<JoinHandle<()>>>,
+    /// Each action picked, from the listener.
+    invoked: (
+        mpsc::UnboundedSender<Invoked>,
+        Mutex<mpsc::UnboundedReceiver<Invoked>>,
+    ),
+}
+
+impl DbusNotifier {
+    pub fn new(app_name: &str) -> DbusNotifier {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        DbusNotifier {
+            app_name: app_name.to_string(),
+            proxy: RefCell::new(None),
+            with_actions: RefCell::new(HashSet::new()),
+            listener: RefCell::new(None),
+            invoked: (sender, Mutex::new(receiver)),
+        }
+    }
+
+    /// Like [`new`](Self::new), but fails unless a notification server can be
+    /// reached on the session bus.
+    pub async fn connect(app_name: &str) -> Result<DbusNotifier, anyhow::Error> {
+        let notifier = DbusNotifier::new(app_name);
+        notifier
+            .proxy()
+            .await?
+            .get_server_information()
+            .await
+            .context("No notification server on the session bus")?;
+        Ok(notifier)
+    }
+
+    async fn proxy(&self) -> Result<NotificationsProxy<'static>, anyhow::Error> {
+        if let Some(proxy) = self.proxy.borrow().clone() {
+            return Ok(proxy);
+        }
+        let connection = zbus::Connection::session()
+            .await
+            .context("Failed to connect to the session bus")?;
+        let proxy = NotificationsProxy::new(&connection).await?;
+        *self.proxy.borrow_mut() = Some(proxy.clone());
+        Ok(proxy)
+    }
+
+    /// Starts forwarding `proxy`'s `ActionInvoked` signals unless that's
+    /// being done already.
+    async fn listen(&self, proxy: &NotificationsProxy<'static>) -> Result<(), anyhow::Error> {
+        if self
+            .listener
+            .borrow()
+            .as_ref()
+            .is_some_and(|listener| !listener.is_finished())
+        {
+            return Ok(());
+        }
+        let mut signals = proxy.receive_action_invoked().await?;
+        let invoked = self.invoked.0.clone();
+        let listener = tokio::spawn(async move {
+            // Ends with the connection, to be started afresh on the next.
+            while let Some(signal) = signals.next().await {
+                match signal.args() {
+                    Ok(args) => {
+                        if invoked.send((args.id, args.action_key)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("Unreadable ActionInvoked signal: {e}"),
+                }
+            }
+        });
+        *self.listener.borrow_mut() = Some(listener);
+        Ok(())
+    }
+}
+
+impl Drop for DbusNotifier {
+    fn drop(&mut self) {
+        if let Some(listener) = self.listener.get_mut() {
+            listener.abort();
+        }
+    }
+}
+
+impl Notifier for DbusNotifier {
+    /// The id the notification server gave the notification.
+    type Handle = u32;
+
+    async fn show(&self, message: &Message, replaces: Option<&u32>) -> Result<u32, anyhow::Error> {
+        let urgency: u8 = match message.urgency {
+            Urgency::Low => 0,
+            Urgency::Normal => 1,
+            Urgency::Critical => 2,
+        };
+        let timeout = match message.timeout {
+            Timeout::Default => -1,
+            Timeout::Persistent => 0,
+            Timeout::After(delay) => delay.as_millis() as i32,
+        };
+        let mut hints = HashMap::from([("urgency", Value::from(urgency))]);
+        if let Some(value) = message.value {
+            hints.insert("value", Value::from(i32::from(value)));
+        }
+        if let Some(sound) = &message.sound {
+            hints.insert("sound-name", Value::from(sound.as_str()));
+        }
+        // Each action is its key and label.
+        let actions: Vec<&str> = message
+            .actions
+            .iter()
+            .flat_map(|(action, label)| [action.key(), label.as_str()])
+            .collect();
+        let proxy = self.proxy().await?;
+        if !actions.is_empty()
+            && let Err(e) = self.listen(&proxy).await
+        {
+            warn!("Not listening for notification actions: {e:#}");
+        }
+        let shown = proxy
+            .notify(
+                &self.app_name,
+                replaces.copied().unwrap_or(0),
+                &message.icon,
+                &message.summary,
+                &message.body,
+                &actions,
+                hints,
+                timeout,
+            )
+            .await;
+        let id = match shown {
+            Ok(id) => id,
+            Err(e) => {
+                // Connect afresh next time, in case the bus went away.
+                self.proxy.borrow_mut().take();
+                return Err(e).context("Notify failed");
+            }
+        };
+        if !actions.is_empty() {
+            self.with_actions.borrow_mut().insert(id);
+        }
+        Ok(id)
+    }
+
+    async fn close(&self, id: &u32) -> Result<(), anyhow::Error> {
+        self.with_actions.borrow_mut().remove(id);
+        self.proxy()
+            .await?
+            .close_notification(*id)
+            .await
+            .context("CloseNotification failed")
+    }
+
+    /// Actions on notifications it didn't show with actions are ignored.
+    async fn action(&self) -> Action {
+        let mut invoked = self.invoked.1.lock().await;
+        while let Some((id, key)) = invoked.recv().await {
+            if self.with_actions.borrow().contains(&id)
+                && let Some(action) = Action::from_key(&key)
+            {
+                return action;
+            }
+        }
+        // The sender is kept alongside, so this isn't reached.
+        std::future::pending().await
+    }
+}
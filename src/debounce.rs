@@ -0,0 +1,94 @@
+//! Holding back a change in whether the battery is charging until it lasts,
+//! so a loose charger flapping between the two doesn't set off warnings and
+//! notifications each time.
+
+use battery_notifier::battery::ChargingState;
+use tracing::debug;
+
+/// The charging state and whether discharging, as read together.
+type Charge = (ChargingState, bool);
+
+#[derive(Default)]
+pub struct ChargeDebounce {
+    /// What is believed, `None` before the first reading.
+    current: Option<Charge>,
+    /// A different reading since, and how many polls in a row it was read.
+    pending: Option<(Charge, u32)>,
+}
+
+impl ChargeDebounce {
+    /// What to act on given `reading`, which is only believed over the last
+    /// one once it has been read `polls` times in a row.
+    pub fn filter(&mut self, reading: Charge, polls: u32) -> Charge {
+        let Some(current) = self.current else {
+            self.current = Some(reading);
+            return reading;
+        };
+        if reading == current {
+            self.pending = None;
+            return current;
+        }
+        let seen = match self.pending {
+            Some((pending, seen)) if pending == reading => seen + 1,
+            _ => 1,
+        };
+        if seen >= polls {
+            self.current = Some(reading);
+            self.pending = None;
+            reading
+        } else {
+            debug!(
+                "Holding back {:?} until it lasts, {seen} of {polls} readings",
+                reading.0
+            );
+            self.pending = Some((reading, seen));
+            current
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHARGING: Charge = (ChargingState::Charging, false);
+    const DISCHARGING: Charge = (ChargingState::Discharging, true);
+    const FULL: Charge = (ChargingState::Full, false);
+
+    #[test]
+    fn first_reading_is_believed() {
+        let mut debounce = ChargeDebounce::default();
+        assert_eq!(debounce.filter(DISCHARGING, 3), DISCHARGING);
+    }
+
+    #[test]
+    fn flapping_is_held_back() {
+        let mut debounce = ChargeDebounce::default();
+        debounce.filter(CHARGING, 3);
+        for reading in [DISCHARGING, DISCHARGING, CHARGING, DISCHARGING, DISCHARGING] {
+            assert_eq!(debounce.filter(reading, 3), CHARGING);
+        }
+        // Each different reading starts the count afresh.
+        assert_eq!(debounce.filter(FULL, 3), CHARGING);
+        assert_eq!(debounce.filter(DISCHARGING, 3), CHARGING);
+    }
+
+    #[test]
+    fn change_is_believed_once_it_lasts() {
+        let mut debounce = ChargeDebounce::default();
+        debounce.filter(CHARGING, 3);
+        assert_eq!(debounce.filter(DISCHARGING, 3), CHARGING);
+        assert_eq!(debounce.filter(DISCHARGING, 3), CHARGING);
+        assert_eq!(debounce.filter(DISCHARGING, 3), DISCHARGING);
+        // And is then what a flap back is held back from.
+        assert_eq!(debounce.filter(CHARGING, 3), DISCHARGING);
+    }
+
+    #[test]
+    fn a_single_poll_believes_every_change() {
+        let mut debounce = ChargeDebounce::default();
+        debounce.filter(CHARGING, 1);
+        assert_eq!(debounce.filter(DISCHARGING, 1), DISCHARGING);
+        assert_eq!(debounce.filter(FULL, 1), FULL);
+    }
+}
@@ -0,0 +1,24 @@
+//! Notifications that are only logged, for `--dry-run`.
+
+use tracing::info;
+
+use crate::notification::{Message, Notifier};
+
+pub struct DryRunNotifier;
+
+impl Notifier for DryRunNotifier {
+    type Handle = ();
+
+    async fn show(&self, message: &Message, _replaces: Option<&()>) -> Result<(), anyhow::Error> {
+        info!(
+            "Would notify: {}: {} ({:?}, {:?})",
+            message.summary, message.body, message.urgency, message.timeout
+        );
+        Ok(())
+    }
+
+    async fn close(&self, _handle: &()) -> Result<(), anyhow::Error> {
+        info!("Would close a notification");
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+//! Exit codes for the failures that scripts and supervisors may want to tell
+//! apart. Anything else exits with 1.
+
+use std::fmt::Display;
+
+/// A failure with its own exit code, given to an error as its context.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// No battery was found, or none could be read.
+    NoBattery,
+    /// The command line or config file is invalid.
+    Config,
+    /// The notification backend couldn't be started.
+    Notifier,
+}
+
+impl Failure {
+    pub fn code(self) -> i32 {
+        match self {
+            Failure::NoBattery => 2,
+            Failure::Config => 3,
+            Failure::Notifier => 4,
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Failure::NoBattery => "Can't read the battery",
+            Failure::Config => "Invalid configuration",
+            Failure::Notifier => "Can't show notifications",
+        })
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// The exit code for `error`: its [`Failure`]'s if it has one, or 1.
+pub fn code(error: &anyhow::Error) -> i32 {
+    error
+        .downcast_ref::<Failure>()
+        .map_or(1, |failure| failure.code())
+}
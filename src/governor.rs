@@ -0,0 +1,137 @@
+//! A limit on how often notifications are shown, whatever they're about, so
+//! several conditions tripping at once don't bury the desktop in them.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::config::Urgency;
+use crate::notification::Message;
+
+pub struct NotificationGovernor {
+    /// The least time between notifications, if limited.
+    min_gap: Option<Duration>,
+    last_shown: Cell<Option<Instant>>,
+    /// The messages held back, oldest first, to be shown as the gap allows.
+    held: RefCell<VecDeque<Message>>,
+}
+
+impl NotificationGovernor {
+    pub fn new(min_gap: Option<Duration>) -> NotificationGovernor {
+        NotificationGovernor {
+            min_gap,
+            last_shown: Cell::new(None),
+            held: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Whether `message` may be shown now. Critical messages always may,
+    /// jumping any held back.
+    pub fn may_show(&self, message: &Message) -> bool {
+        message.urgency == Urgency::Critical || self.gap_passed()
+    }
+
+    fn gap_passed(&self) -> bool {
+        self.min_gap.is_none_or(|gap| {
+            self.last_shown
+                .get()
+                .is_none_or(|last| last.elapsed() >= gap)
+        })
+    }
+
+    /// Counts a notification as shown now, starting the gap before the next.
+    pub fn shown(&self) {
+        self.last_shown.set(Some(Instant::now()));
+    }
+
+    /// Holds `message` back until the gap allows it, in place of any held
+    /// with the same summary so only the latest of those is shown.
+    pub fn hold(&self, message: Message) {
+        info!(
+            "Holding back {:?}, too soon after the last notification",
+            message.summary
+        );
+        let mut held = self.held.borrow_mut();
+        match held.iter_mut().find(|held| held.summary == message.summary) {
+            Some(held) => *held = message,
+            None => held.push_back(message),
+        }
+    }
+
+    /// The oldest message held back, taken if the gap allows it now.
+    pub fn take_due(&self) -> Option<Message> {
+        if self.gap_passed() {
+            self.held.borrow_mut().pop_front()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notification::Timeout;
+
+    fn message(summary: &str, urgency: Urgency) -> Message {
+        Message {
+            summary: summary.to_string(),
+            body: String::new(),
+            icon: String::new(),
+            urgency,
+            timeout: Timeout::Default,
+            value: None,
+            sound: None,
+            actions: Vec::new(),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn holds_back_until_the_gap_has_passed() {
+        let governor = NotificationGovernor::new(Some(Duration::from_secs(60)));
+        let low = message("Battery Low!", Urgency::Normal);
+        assert!(governor.may_show(&low));
+        governor.shown();
+        assert!(!governor.may_show(&low));
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(governor.may_show(&low));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn critical_jumps_the_queue() {
+        let governor = NotificationGovernor::new(Some(Duration::from_secs(60)));
+        governor.shown();
+        governor.hold(message("Fast drain", Urgency::Normal));
+        assert!(governor.may_show(&message("Battery Critical!", Urgency::Critical)));
+        assert!(governor.take_due().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shows_held_back_messages_in_turn() {
+        let governor = NotificationGovernor::new(Some(Duration::from_secs(60)));
+        governor.shown();
+        governor.hold(message("Fast drain", Urgency::Normal));
+        governor.hold(message("Worn battery", Urgency::Normal));
+        governor.hold(message("Fast drain", Urgency::Low));
+        tokio::time::advance(Duration::from_secs(60)).await;
+        let first = governor.take_due().unwrap();
+        assert_eq!(first.summary, "Fast drain");
+        // The later of the two held with the same summary.
+        assert_eq!(first.urgency, Urgency::Low);
+        governor.shown();
+        assert!(governor.take_due().is_none());
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(governor.take_due().unwrap().summary, "Worn battery");
+        assert!(governor.take_due().is_none());
+    }
+
+    #[test]
+    fn unlimited_without_a_gap() {
+        let governor = NotificationGovernor::new(None);
+        governor.shown();
+        assert!(governor.may_show(&message("Battery Low!", Urgency::Normal)));
+    }
+}
@@ -0,0 +1,53 @@
+//! A systemd-logind inhibitor lock, so the system doesn't suspend on idle
+//! while a critical warning is waiting to be seen.
+
+use std::process::Stdio;
+
+use anyhow::Context;
+use tokio::process::{Child, Command};
+use tracing::{info, warn};
+
+/// Holds an idle and sleep inhibitor while taken.
+///
+/// logind's `org.freedesktop.login1.Manager.Inhibit` hands back a file
+/// descriptor that holds the lock for as long as it is open, which busctl
+/// can't keep open, so `systemd-inhibit` makes the call and holds it in a
+/// child process instead. The child is killed if this is dropped.
+#[derive(Default)]
+pub struct Inhibitor {
+    child: Option<Child>,
+}
+
+impl Inhibitor {
+    /// Takes the lock, unless it is already held.
+    pub fn take(&mut self, why: &str) -> Result<(), anyhow::Error> {
+        if self.child.is_some() {
+            return Ok(());
+        }
+        let child = Command::new("systemd-inhibit")
+            .args([
+                "--what=idle:sleep",
+                "--who=battery-notifier",
+                "--mode=block",
+            ])
+            .arg(format!("--why={why}"))
+            .args(["sleep", "infinity"])
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to run systemd-inhibit")?;
+        info!("Inhibiting suspend");
+        self.child = Some(child);
+        Ok(())
+    }
+
+    /// Releases the lock, if held.
+    pub async fn release(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            match child.kill().await {
+                Ok(()) => info!("No longer inhibiting suspend"),
+                Err(e) => warn!("Failed to stop systemd-inhibit: {e}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Installing the daemon as a systemd user unit, so it starts at login.
+
+use std::env;
+use std::fs::{create_dir_all, remove_file, write};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, anyhow};
+
+use crate::config::config_home;
+
+/// The unit shipped alongside the source, whose `ExecStart` is replaced with
+/// the running executable.
+const UNIT: &str = include_str!("../battery-notifier.service");
+const UNIT_NAME: &str = "battery-notifier.service";
+/// Where `systemctl --user enable` links the unit, given its `WantedBy`.
+const WANTS_DIR: &str = "default.target.wants";
+
+fn unit_dir() -> Result<PathBuf, anyhow::Error> {
+    config_home()
+        .map(|dir| dir.join("systemd").join("user"))
+        .ok_or_else(|| anyhow!("Neither XDG_CONFIG_HOME nor HOME is set"))
+}
+
+/// The unit file, starting the executable at `exe`.
+fn unit(exe: &Path) -> String {
+    let exe = exe.display().to_string();
+    let exe = if exe.contains(char::is_whitespace) {
+        format!("\"{exe}\"")
+    } else {
+        exe
+    };
+    UNIT.lines()
+        .map(|line| {
+            if line.starts_with("ExecStart=") {
+                format!("ExecStart={exe}\n")
+            } else {
+                format!("{line}\n")
+            }
+        })
+        .collect()
+}
+
+/// Writes the user unit for the running executable and says how to enable
+/// it.
+pub fn install() -> Result<(), anyhow::Error> {
+    let exe = env::current_exe().context("Failed to find the running executable")?;
+    let dir = unit_dir()?;
+    create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(UNIT_NAME);
+    write(&path, unit(&exe)).with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("Installed {}", path.display());
+    println!("To start it now and at each login, run:");
+    println!("    systemctl --user daemon-reload");
+    println!("    systemctl --user enable --now {UNIT_NAME}");
+    Ok(())
+}
+
+/// Removes the user unit, and the link enabling it if there is one.
+pub fn uninstall() -> Result<(), anyhow::Error> {
+    let dir = unit_dir()?;
+    let path = dir.join(UNIT_NAME);
+    match remove_file(&path) {
+        Ok(()) => println!("Removed {}", path.display()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            println!("Nothing installed at {}", path.display());
+            return Ok(());
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to remove {}", path.display())),
+    }
+    let link = dir.join(WANTS_DIR).join(UNIT_NAME);
+    match remove_file(&link) {
+        Ok(()) => println!("Removed {}", link.display()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("Failed to remove {}", link.display())),
+    }
+    println!("To stop any running instance, run:");
+    println!("    systemctl --user stop {UNIT_NAME}");
+    println!("    systemctl --user daemon-reload");
+    Ok(())
+}
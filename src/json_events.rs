@@ -0,0 +1,70 @@
+//! `--json-events`: battery events printed to stdout as one JSON object per
+//! line, for piping into another program instead of notifying.
+
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use battery_notifier::battery::BatterySource;
+use battery_notifier::monitor::{BatteryEvent, BatteryMonitor, EventSink};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::config::Settings;
+
+/// Writes each event as a line of JSON to stdout.
+struct JsonLines;
+
+/// An event as printed, with its time in seconds since the Unix epoch.
+#[derive(Serialize)]
+struct Line {
+    timestamp: u64,
+    event: &'static str,
+    level: u8,
+}
+
+impl EventSink for JsonLines {
+    async fn send(&mut self, event: BatteryEvent) -> Result<(), anyhow::Error> {
+        let kind = match event {
+            BatteryEvent::LowReached(_) => "low_reached",
+            BatteryEvent::CriticalReached(_) => "critical_reached",
+            BatteryEvent::Charging(_) => "charging",
+            BatteryEvent::Full(_) => "full",
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let line = Line {
+            timestamp,
+            event: kind,
+            level: event.level().level(),
+        };
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", serde_json::to_string(&line)?)?;
+        // Flush each line, as a pipe would otherwise hold them back.
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Prints the events from `source` until stdout is closed, skipping polls
+/// that fail.
+pub async fn print_events<S: BatterySource>(
+    source: &S,
+    settings: &Settings,
+) -> Result<(), anyhow::Error> {
+    let mut monitor = BatteryMonitor::builder(source)
+        .low(settings.low_level.clone())
+        .critical(settings.critical_level.clone())
+        .full(settings.full_level.clone())
+        // The shortest of adaptive intervals, so no crossing is reported late.
+        .interval(settings.poll_interval.interval(None))
+        .build()?;
+    let mut sink = JsonLines;
+    loop {
+        match monitor.next_event().await {
+            Ok(event) => sink.send(event).await?,
+            Err(e) => warn!("Skipping poll: {e:#}"),
+        }
+    }
+}
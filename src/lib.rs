@@ -0,0 +1,13 @@
+//! Reading battery levels from the kernel, as used by the battery-notifier
+//! daemon.
+//!
+//! [`sysfs::SysfsSource`] reads the batteries under `/sys/class/power_supply`
+//! as a [`battery::BatterySource`], and [`battery::battery_snapshot_stream`]
+//! polls one for the level and charging state. [`upower::UPowerSource`] reads
+//! them from UPower instead. [`monitor::BatteryMonitor`] wraps the polling
+//! up into threshold and charging events for other programs to embed.
+
+pub mod battery;
+pub mod monitor;
+pub mod sysfs;
+pub mod upower;
@@ -0,0 +1,263 @@
+//! Notifications shown through libnotify.
+//!
+//! They're shown without their actions, as libnotify only calls those back
+//! from a running GLib main loop; `--backend dbus` shows them instead.
+
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use glib::ToVariant;
+use libnotify::{Notification, Urgency};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::config;
+use crate::notification::{Message, Notifier, Timeout};
+
+impl From<config::Urgency> for Urgency {
+    fn from(urgency: config::Urgency) -> Urgency {
+        match urgency {
+            config::Urgency::Low => Urgency::Low,
+            config::Urgency::Normal => Urgency::Normal,
+            config::Urgency::Critical => Urgency::Critical,
+        }
+    }
+}
+
+/// What the libnotify thread is asked to do.
+enum Request {
+    /// Shows the message, replacing the notification by the given id if any,
+    /// and replies with the id of the one shown.
+    Show(Message, Option<u64>, oneshot::Sender<Result<u64, String>>),
+    Close(u64, oneshot::Sender<Result<(), String>>),
+    /// The handle to the notification by this id was dropped.
+    Forget(u64),
+}
+
+/// Runs libnotify on a thread of its own, as its objects can't leave the
+/// thread they were made on and it blocks while the notification server
+/// answers, so a hung server holds up only the notifications rather than
+/// polling too. It's shut down once the last sender is dropped.
+fn libnotify_thread(app_name: String) -> Result<mpsc::UnboundedSender<Request>, anyhow::Error> {
+    let (sender, mut requests) = mpsc::unbounded_channel();
+    let (initialized, init) = std::sync::mpsc::channel();
+    std::thread::Builder::new()
+        .name("libnotify".to_string())
+        .spawn(move || {
+            let result = libnotify::init(&app_name);
+            let failed = result.is_err();
+            let _ = initialized.send(result);
+            if failed {
+                return;
+            }
+            let mut notifications = HashMap::new();
+            let mut next_id = 0;
+            while let Some(request) = requests.blocking_recv() {
+                match request {
+                    Request::Show(message, replaces, reply) => {
+                        let shown = show(&message, replaces.and_then(|id| notifications.get(&id)))
+                            .map(|notification| {
+                                // A fresh id even for one replaced, as the
+                                // replaced handle is forgotten once dropped.
+                                next_id += 1;
+                                notifications.insert(next_id, notification);
+                                next_id
+                            });
+                        let _ = reply.send(shown);
+                    }
+                    Request::Close(id, reply) => {
+                        let closed = match notifications.get(&id) {
+                            Some(notification) => notification.close().map_err(|e| e.to_string()),
+                            None => Ok(()),
+                        };
+                        let _ = reply.send(closed);
+                    }
+                    Request::Forget(id) => {
+                        notifications.remove(&id);
+                    }
+                }
+            }
+            libnotify::uninit();
+        })?;
+    match init.recv()? {
+        Ok(()) => Ok(sender),
+        Err(e) => bail!("Failed to initialize libnotify with err = {e}"),
+    }
+}
+
+/// Shows `message` on the libnotify thread, updating `replaces` in place if
+/// given.
+fn show(message: &Message, replaces: Option<&Notification>) -> Result<Notification, String> {
+    let notification = match replaces {
+        Some(notification) => {
+            notification
+                .update(
+                    message.summary.as_str(),
+                    message.body.as_str(),
+                    message.icon.as_str(),
+                )
+                .map_err(|e| format!("Failed to update notification: {e}"))?;
+            notification.clone()
+        }
+        None => Notification::new(
+            message.summary.as_str(),
+            message.body.as_str(),
+            message.icon.as_str(),
+        ),
+    };
+    // These only apply to the notification as next shown, so they must be
+    // set before `show`, or the first warning ignores them.
+    notification.set_urgency(message.urgency.into());
+    notification.set_hint(
+        "value",
+        message.value.map(|value| i32::from(value).to_variant()),
+    );
+    notification.set_hint(
+        "sound-name",
+        message.sound.as_deref().map(ToVariant::to_variant),
+    );
+    match message.timeout {
+        Timeout::Default => {}
+        Timeout::Persistent => notification.set_timeout(i32::MAX),
+        Timeout::After(delay) => notification.set_timeout(delay.as_millis() as i32),
+    }
+    notification.show().map_err(|e| e.to_string())?;
+    Ok(notification)
+}
+
+/// A notification shown through libnotify, forgotten by its thread once
+/// dropped.
+pub struct LibnotifyHandle {
+    id: u64,
+    requests: mpsc::UnboundedSender<Request>,
+}
+
+impl Drop for LibnotifyHandle {
+    fn drop(&mut self) {
+        let _ = self.requests.send(Request::Forget(self.id));
+    }
+}
+
+/// Initializes libnotify, on its own thread, for as long as it lives.
+pub struct LibnotifyNotifier {
+    requests: mpsc::UnboundedSender<Request>,
+}
+
+impl LibnotifyNotifier {
+    pub fn new(app_name: &str) -> Result<LibnotifyNotifier, anyhow::Error> {
+        Ok(LibnotifyNotifier {
+            requests: libnotify_thread(app_name.to_string())?,
+        })
+    }
+
+    /// Sends `request` made with a reply channel to the libnotify thread, and
+    /// waits for its reply.
+    async fn call<T>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<Result<T, String>>) -> Request,
+    ) -> Result<T, anyhow::Error> {
+        let (reply, replied) = oneshot::channel();
+        self.requests
+            .send(request(reply))
+            .map_err(|_| anyhow!("The libnotify thread has stopped"))?;
+        replied
+            .await
+            .map_err(|_| anyhow!("The libnotify thread has stopped"))?
+            .map_err(|e| anyhow!(e))
+    }
+}
+
+impl Notifier for LibnotifyNotifier {
+    type Handle = LibnotifyHandle;
+
+    async fn show(
+        &self,
+        message: &Message,
+        replaces: Option<&LibnotifyHandle>,
+    ) -> Result<LibnotifyHandle, anyhow::Error> {
+        let replaces = replaces.map(|handle| handle.id);
+        let id = self
+            .call(|reply| Request::Show(message.clone(), replaces, reply))
+            .await?;
+        Ok(LibnotifyHandle {
+            id,
+            requests: self.requests.clone(),
+        })
+    }
+
+    async fn close(&self, handle: &LibnotifyHandle) -> Result<(), anyhow::Error> {
+        self.call(|reply| Request::Close(handle.id, reply)).await
+    }
+}
+
+/// How many times to try initializing libnotify at startup, e.g. while the
+/// desktop session is still starting, before carrying on without it.
+const INIT_ATTEMPTS: u32 = 5;
+/// How long to wait after the first failed attempt, doubling each time.
+const INIT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// libnotify, or notifications failing while it can't be initialized.
+/// Initializing is tried again with each notification until it works.
+pub struct FallbackNotifier {
+    app_name: String,
+    notifier: OnceCell<LibnotifyNotifier>,
+}
+
+impl FallbackNotifier {
+    /// Tries to initialize libnotify a few times, backing off between
+    /// attempts, and carries on without it if that keeps failing.
+    pub async fn new(app_name: &str) -> FallbackNotifier {
+        let notifier = OnceCell::new();
+        for attempt in 0..INIT_ATTEMPTS {
+            match LibnotifyNotifier::new(app_name) {
+                Ok(libnotify) => {
+                    let _ = notifier.set(libnotify);
+                    break;
+                }
+                Err(e) if attempt + 1 < INIT_ATTEMPTS => {
+                    let retry = INIT_BACKOFF * 2u32.pow(attempt);
+                    warn!("{e:#}, retrying in {}s", retry.as_secs());
+                    sleep(retry).await;
+                }
+                Err(e) => warn!("{e:#}, carrying on without it for now"),
+            }
+        }
+        FallbackNotifier {
+            app_name: app_name.to_string(),
+            notifier,
+        }
+    }
+
+    fn notifier(&self) -> Option<&LibnotifyNotifier> {
+        if let Some(notifier) = self.notifier.get() {
+            return Some(notifier);
+        }
+        let notifier = LibnotifyNotifier::new(&self.app_name).ok()?;
+        Some(self.notifier.get_or_init(|| notifier))
+    }
+}
+
+impl Notifier for FallbackNotifier {
+    type Handle = LibnotifyHandle;
+
+    async fn show(
+        &self,
+        message: &Message,
+        replaces: Option<&LibnotifyHandle>,
+    ) -> Result<LibnotifyHandle, anyhow::Error> {
+        let notifier = self
+            .notifier()
+            .ok_or_else(|| anyhow!("libnotify still can't be initialized"))?;
+        notifier.show(message, replaces).await
+    }
+
+    async fn close(&self, handle: &LibnotifyHandle) -> Result<(), anyhow::Error> {
+        match self.notifier.get() {
+            Some(notifier) => notifier.close(handle).await,
+            None => Ok(()),
+        }
+    }
+}
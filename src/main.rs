@@ -1,184 +1,1646 @@
-use std::fmt::{Debug, Display};
-use std::time::{Duration, Instant};
+mod command;
+mod command_fallback;
+mod config;
+mod daemon;
+mod dbus_fallback;
+mod dbus_notifier;
+mod debounce;
+mod dry_run_notifier;
+mod exit;
+mod governor;
+mod inhibit;
+mod install;
+mod json_events;
+#[cfg(feature = "libnotify")]
+mod libnotify_notifier;
+mod messages;
+mod metrics;
+mod notification;
+mod quiet_hours;
+mod session;
+mod signals;
+mod socket;
+mod state;
+mod watch;
 
-use anyhow::{anyhow, bail};
-use async_stream::stream;
-use contracts::{ensures, requires};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, anyhow, bail};
+use battery_notifier::battery::{
+    BatteryLevel, BatterySnapshot, BatterySource, CapacityLevel, ChargeRate, ChargingState,
+    DrainRate, battery_snapshot_stream, smoothed_levels,
+};
+use battery_notifier::sysfs::{Battery, SysfsSource, set_charge_thresholds};
+use battery_notifier::upower::UPowerSource;
+use clap::Parser;
 use futures::StreamExt;
-use futures::stream::BoxStream;
-use libnotify::{Notification, Urgency};
-use tokio::fs::read;
+use futures::stream::LocalBoxStream;
+use sd_notify::NotifyState as SystemdState;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::time::sleep;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+use crate::command::{log_shell, spawn_shell};
+use crate::command_fallback::CommandFallback;
+use crate::config::{
+    Args, Backend, Mode, PollInterval, Settings, Source, ThresholdSource, Tier, Urgency,
+};
+#[cfg(feature = "libnotify")]
+use crate::dbus_fallback::with_fallbacks;
+use crate::dbus_notifier::DbusNotifier;
+use crate::debounce::ChargeDebounce;
+use crate::dry_run_notifier::DryRunNotifier;
+use crate::exit::Failure;
+use crate::inhibit::Inhibitor;
+#[cfg(feature = "libnotify")]
+use crate::libnotify_notifier::{FallbackNotifier, LibnotifyNotifier};
+use crate::notification::{
+    Action, DISMISS_FOR, NotificationService, Notifier, WarningDetails, format_estimate,
+};
+use crate::session::Session;
+use crate::socket::Reading;
 
-const APP_NAME: &'static str = "battery-notifier";
-const BATTERY_CHARGING: &'static str = "/sys/class/power_supply/BAT0/status";
-const BATTERY_LEVEL_NOW: &'static str = "/sys/class/power_supply/BAT0/energy_now";
-const BATTERY_LEVEL_FULL: &'static str = "/sys/class/power_supply/BAT0/energy_full";
-const CRITICAL_BATTERY_LEVEL: BatteryLevel = BatteryLevel(6);
-const LOW_BATTERY_LEVEL: BatteryLevel = BatteryLevel(15);
+const APP_NAME: &str = "battery-notifier";
+/// How long `--suspend-warning` gives to plug in before suspending.
+const SUSPEND_WARNING: Duration = Duration::from_secs(30);
+/// How long to wait after the first failed poll, doubling with each further
+/// failure up to the poll interval.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+/// How many readings the drain and charge rates are measured over.
+const DRAIN_WINDOW: usize = 4;
+/// How many readings in a row must drain too fast, or charge too slowly,
+/// before warning, so a brief burst of load doesn't.
+const DRAIN_SAMPLES: u32 = 3;
+/// How often to log the battery while its level doesn't change.
+const STEADY_LOG_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// The level below which `--discharge-grace` no longer holds back warnings.
+const GRACE_DIRE_LEVEL: BatteryLevel = BatteryLevel::saturating(3);
+/// How often a sleep between polls checks whether the system was suspended.
+const RESUME_CHECK: Duration = Duration::from_secs(30);
+/// How far the wall clock must run ahead of the monotonic clock, which stops
+/// while suspended, to count as a resume rather than the clock being set.
+const RESUME_GAP: Duration = Duration::from_secs(60);
+/// How long the batteries are given to settle after a resume before they're
+/// read.
+const RESUME_SETTLE: Duration = Duration::from_secs(2);
 
-async fn battery_charging() -> Result<bool, anyhow::Error> {
-    let raw_charging_level = String::from_utf8(read(BATTERY_CHARGING).await?)?;
-    match raw_charging_level.trim() {
-        "Charging" => Ok(true),
-        "Unknown" | "Discharging" | "Not charging" | "Full" => Ok(false),
-        _ => Err(anyhow!("Invalid charging status")),
+/// Sends `state` to systemd. This does nothing unless the daemon was started
+/// by systemd with `NOTIFY_SOCKET` set, e.g. by a `Type=notify` unit.
+fn notify_systemd(state: SystemdState) {
+    if let Err(e) = sd_notify::notify(&[state]) {
+        warn!("Failed to notify systemd: {e}");
     }
 }
 
-async fn battery_energy_full() -> Result<f32, anyhow::Error> {
-    let raw_battery_level = String::from_utf8(read(BATTERY_LEVEL_FULL).await?)?;
-    // println!("Raw battery level: {raw_battery_level}");
-    let res: u32 = raw_battery_level.trim().parse()?;
+/// Sleeps for `duration`, pinging the systemd watchdog, if enabled, at least
+/// twice per watchdog timeout so a long poll interval doesn't trip it. A
+/// hung battery read still stops the pings.
+async fn sleep_with_watchdog(duration: Duration, watchdog: Option<Duration>) {
+    let Some(watchdog) = watchdog else {
+        return sleep(duration).await;
+    };
 
-    Ok(res as f32)
+    let deadline = tokio::time::Instant::now() + duration;
+    loop {
+        notify_systemd(SystemdState::Watchdog);
+        let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        sleep(left.min(watchdog / 2)).await;
+    }
 }
 
-async fn battery_energy_now() -> Result<f32, anyhow::Error> {
-    let raw_battery_level = String::from_utf8(read(BATTERY_LEVEL_NOW).await?)?;
-    // println!("Raw battery level: {raw_battery_level}");
-    let res: u32 = raw_battery_level.trim().parse()?;
-
-    Ok(res as f32)
+/// Sleeps between polls like [`sleep_with_watchdog`], but wakes early if the
+/// system resumes from suspend meanwhile, returning whether it did.
+async fn sleep_until_poll(duration: Duration, watchdog: Option<Duration>) -> bool {
+    let deadline = tokio::time::Instant::now() + duration;
+    let (mut wall, mut monotonic) = (SystemTime::now(), tokio::time::Instant::now());
+    loop {
+        let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if left.is_zero() {
+            return false;
+        }
+        sleep_with_watchdog(left.min(RESUME_CHECK), watchdog).await;
+        let (wall_now, monotonic_now) = (SystemTime::now(), tokio::time::Instant::now());
+        let wall_elapsed = wall_now.duration_since(wall).unwrap_or_default();
+        if wall_elapsed > monotonic_now.duration_since(monotonic) + RESUME_GAP {
+            return true;
+        }
+        (wall, monotonic) = (wall_now, monotonic_now);
+    }
 }
 
-struct NotificationService;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NotificationState {
+    /// Last warned at the given index into [`Settings::tiers`] at the given
+    /// time, after repeating the warning at that tier the given number of
+    /// times without the battery recovering, and dismissed until the given
+    /// time, if it was.
+    Notified(usize, Instant, u32, Option<Instant>),
+    Charging,
+    /// Notified at the given time that charging reached the full level;
+    /// cleared once the battery discharges below it again.
+    Full(Instant),
+    /// Suspended the system; nothing more is done until charging resumes.
+    Suspended,
+    NeverNotified,
+}
 
-impl NotificationService {
-    fn new(app_name: &str) -> Result<NotificationService, anyhow::Error> {
-        if let Err(e) = libnotify::init(app_name) {
-            bail!("Failed to initialize libnotify with err = {e}");
+/// `state` in words, for `--watch`.
+fn describe_state(
+    state: NotificationState,
+    level: &BatteryLevel,
+    settings: &Settings,
+    now: Instant,
+) -> String {
+    match state {
+        NotificationState::Notified(tier, t, _, dismissed) => {
+            let tier = &settings.tiers[tier];
+            let ago = now.duration_since(t).as_secs();
+            let again = tier
+                .frequency_at(level)
+                .as_secs()
+                .saturating_sub(ago)
+                .max(dismissed.map_or(0, |until| until.duration_since(now).as_secs()));
+            format!(
+                "warned at {} {ago}s ago, again in {again}s unless above {}",
+                tier.level, tier.clear
+            )
+        }
+        NotificationState::Charging => "charging".to_string(),
+        NotificationState::Full(t) => {
+            format!("notified full {}s ago", now.duration_since(t).as_secs())
         }
-        Ok(NotificationService)
+        NotificationState::Suspended => "suspended".to_string(),
+        NotificationState::NeverNotified => "not warned".to_string(),
     }
+}
 
-    fn notify_critical_battery(&self, level: BatteryLevel) -> Result<(), anyhow::Error> {
-        let notification = Notification::new(
-            "Battery Critical!",
-            format!("Battery critical at {}", level).as_str(),
-            "battery-caution",
-        );
-        notification.set_urgency(Urgency::Critical);
-        notification.set_timeout(i32::MAX);
-        notification.show()?;
-        Ok(())
+/// Tracks the temperature warning, which is independent of the charge level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TemperatureState {
+    Normal,
+    /// Warned at the given time; cleared once the battery cools down.
+    NotifiedHot(Instant),
+}
+
+async fn battery_notifier<S: BatterySource + 'static, N: Notifier>(
+    source: S,
+    notifier: N,
+    mut settings: Settings,
+) -> Result<(), anyhow::Error> {
+    use NotificationState::*;
+
+    let mut notification_service = NotificationService::new(
+        notifier,
+        settings.graded_icons,
+        settings.progress_hint,
+        settings.messages.clone(),
+        settings.max_notify_rate,
+    );
+
+    match source.info().await {
+        Ok(Some(info)) => info!("Battery: {info}"),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to read the battery's model: {e:#}"),
+    }
+    if let Ok(Some(cycles)) = source.cycle_count().await {
+        info!("Battery cycle count: {cycles}");
+    }
+    if let Some(health_warn) = settings.health_warn
+        && let Ok(Some(health)) = source.health_percent().await
+        && health < health_warn
+    {
+        warn!("Battery health is {health:.0}%");
+        notification_service.notify_poor_health(health).await?;
     }
 
-    fn notify_low_battery(&self, level: BatteryLevel) -> Result<(), anyhow::Error> {
-        let notification = Notification::new(
-            "Battery Low!",
-            format!("Battery low at {}", level).as_str(),
-            "battery-low",
-        );
-        notification.show()?;
-        notification.set_urgency(Urgency::Critical);
-        notification.set_timeout(i32::MAX);
-        Ok(())
+    // Shared with the poller's task.
+    let source = Rc::new(source);
+    // --dry-run and --watch only preview, so they leave the state and socket
+    // of a daemon that may be running alongside alone.
+    let preview = settings.dry_run || settings.watch;
+    // Carry on from the state saved by a previous run, so a restart doesn't
+    // repeat a warning before its frequency has passed.
+    let state_path = state::state_path().filter(|_| !preview);
+    let mut notification_state = match state_path
+        .as_deref()
+        .map(|path| state::load(path, &settings.tiers))
+    {
+        Some(Ok(Some(state))) => {
+            debug!("Restored notification state {state:?}");
+            state
+        }
+        Some(Err(e)) => {
+            warn!("Ignoring saved notification state: {e:#}");
+            NeverNotified
+        }
+        _ => NeverNotified,
+    };
+    let mut temperature_state = TemperatureState::Normal;
+    let mut drain = DrainRate::new(DRAIN_WINDOW);
+    // Readings in a row draining too fast, and when that was last warned of.
+    let mut fast_drain = 0;
+    let mut drain_notified: Option<Instant> = None;
+    let mut charge_rate = ChargeRate::new(DRAIN_WINDOW);
+    // Likewise for readings charging too slowly.
+    let mut slow_charge = 0;
+    let mut slow_charge_notified: Option<Instant> = None;
+    // Whether the charge limit was reached since last unplugged below it.
+    let mut over_charge_limit = false;
+    // The lowest step reached since last charging, if notifying at steps.
+    let mut last_step = None;
+    // The level and charging state last logged at info, and when.
+    let mut last_logged: Option<(BatteryLevel, bool, Instant)> = None;
+    let mut was_discharging = None;
+    let mut unplugged_at = None;
+    let mut charge_debounce = ChargeDebounce::default();
+    let mut was_charging = None;
+    // For the suspend picked on a warning.
+    let mut last_level = None;
+    let watchdog = sd_notify::watchdog_enabled();
+    let snooze = signals::snooze_signals(settings.snooze)?;
+    let mut reload = signals::reload_signal()?;
+    // The socket is removed once this returns.
+    let (latest, mut profile_switches, _listener) = match settings
+        .socket
+        .as_deref()
+        .filter(|_| !preview)
+        .map(socket::serve)
+        .transpose()?
+    {
+        Some((latest, switches, listener)) => (Some(latest), Some(switches), Some(listener)),
+        None => (None, None, None),
+    };
+    // The profile last switched to over the socket, which a reload keeps.
+    let mut switched_profile = None;
+    let metrics = match settings.metrics_addr {
+        Some(addr) => Some(metrics::serve(addr).await?),
+        None => None,
+    };
+    let mut inhibitor = Inhibitor::default();
+    let mut session = Session::start();
+    let mut ready = false;
+    let mut absent = false;
+    let mut failures: u32 = 0;
+
+    // Stop cleanly when systemd stops the unit or on Ctrl-C, so that warnings
+    // still on screen are closed and the notifier is shut down.
+    let shutdown = signals::shutdown_signal()?;
+    // The battery is polled on its own task, so a slow notification doesn't
+    // hold up the next reading or the watchdog's pings.
+    let (sender, mut polls) = mpsc::channel(1);
+    let intervals = Rc::new(RefCell::new(settings.poll_interval.clone()));
+    let mut poller = tokio::task::spawn_local({
+        let (source, intervals, smooth) = (source.clone(), intervals.clone(), settings.smooth);
+        async move {
+            let stream = smoothed_levels(battery_snapshot_stream(&*source), smooth);
+            poll_battery(stream, &intervals, watchdog, sender).await;
+        }
+    });
+    let monitor = async {
+        loop {
+            let Poll {
+                reading,
+                next: poll_interval,
+                resumed,
+            } = tokio::select! {
+                poll = polls.recv() => match poll {
+                    Some(poll) => poll,
+                    None => break,
+                },
+                action = notification_service.action() => {
+                    match action {
+                        Action::Suspend => {
+                            let Some(level) = &last_level else { continue };
+                            info!("Suspending, as picked on a warning");
+                            inhibitor.release().await;
+                            if settings.dry_run {
+                                log_shell(&settings.suspend_cmd, level);
+                            } else {
+                                spawn_shell(&settings.suspend_cmd, level);
+                            }
+                            // Like --suspend-at, so that doesn't suspend
+                            // again once resumed this discharge.
+                            notification_state = Suspended;
+                        }
+                        Action::Dismiss => {
+                            if let Notified(tier, t, repeats, _) = notification_state {
+                                info!("Warning dismissed for {}m", DISMISS_FOR.as_secs() / 60);
+                                let until = tokio::time::Instant::now().into_std() + DISMISS_FOR;
+                                notification_state = Notified(tier, t, repeats, Some(until));
+                            }
+                        }
+                    }
+                    save_state(state_path.as_deref(), notification_state, &settings.tiers);
+                    continue;
+                }
+                Some(()) = reload.recv() => {
+                    notification_state =
+                        reload_settings(&mut settings, notification_state, switched_profile.clone());
+                    *intervals.borrow_mut() = settings.poll_interval.clone();
+                    continue;
+                }
+                Some(name) = next_switch(&mut profile_switches) => {
+                    info!("Switching to the {name} profile");
+                    notification_state =
+                        reload_settings(&mut settings, notification_state, Some(name.clone()));
+                    *intervals.borrow_mut() = settings.poll_interval.clone();
+                    if settings.profile.as_ref() == Some(&name) {
+                        switched_profile = Some(name);
+                    }
+                    continue;
+                }
+            };
+            // Tokio's clock is the monotonic one, but tests can pause it.
+            let now = tokio::time::Instant::now().into_std();
+            // Time spent suspended doesn't count towards repeating a
+            // warning, and would throw the drain rate off, so start afresh.
+            // Having suspended at --suspend-at is kept, as that's only
+            // done once per discharge, until charging is seen.
+            if resumed {
+                info!("Resumed from suspend");
+                if matches!(notification_state, Notified(..)) {
+                    notification_state = NeverNotified;
+                }
+                unplugged_at = None;
+                drain.reset();
+                fast_drain = 0;
+                charge_rate.reset();
+                slow_charge = 0;
+            }
+            let previous_state = notification_state;
+            // Alerts are held back while snoozed, but suspending isn't.
+            let snoozed = snooze.borrow().is_some_and(|until| now < until);
+            let quiet = settings.quiet_hours.is_some_and(|window| window.now());
+            // Quiet hours hold back all but the critical warning and any more
+            // severe, whatever their urgency.
+            let held_quiet = |tier: &Tier| quiet && tier.level > settings.critical_level;
+            let reading = match reading {
+                Ok(Some(snapshot)) => Ok(snapshot),
+                Ok(None) => {
+                    if !absent {
+                        warn!("No battery present, pausing notifications");
+                        absent = true;
+                        was_discharging = None;
+                        was_charging = None;
+                        charge_debounce = ChargeDebounce::default();
+                        if let Some(latest) = &latest {
+                            *latest.lock().unwrap() = None;
+                        }
+                        if let Some(metrics) = &metrics {
+                            let mut metrics = metrics.lock().unwrap();
+                            metrics.level = None;
+                            metrics.charging = None;
+                            metrics.power_watts = None;
+                        }
+                    }
+                    continue;
+                }
+                Err(e) => Err(e),
+            };
+            if absent {
+                info!("Battery present again, resuming notifications");
+                absent = false;
+            }
+
+            // Skip any poll where the battery can't be read, e.g. while sysfs is
+            // briefly unavailable.
+            let BatterySnapshot {
+                level,
+                charging_state,
+                discharging,
+                power_draw_watts: watts,
+            } = match reading {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    failures += 1;
+                    if settings.max_failures.is_some_and(|max| failures >= max) {
+                        return Err(e.context(format!("Giving up after {failures} failed polls")));
+                    }
+                    warn!(
+                        "Skipping poll: {e:#}, retrying in {}s",
+                        poll_interval.as_secs()
+                    );
+                    continue;
+                }
+            };
+            failures = 0;
+            last_level = Some(level.clone());
+            let (charging_state, discharging) =
+                charge_debounce.filter((charging_state, discharging), settings.charge_debounce);
+            let battery_charging = charging_state == ChargingState::Charging;
+            session.record(now, &level, discharging, watts);
+            let reading = match watts {
+                Some(watts) => format!(
+                    "{level} ({watts:.1} W {})",
+                    if battery_charging {
+                        "charging"
+                    } else {
+                        "discharging"
+                    }
+                ),
+                None => level.to_string(),
+            };
+            // Only log a steady reading now and then, but every poll at debug.
+            if last_logged.as_ref().is_none_or(|(logged, charging, t)| {
+                *logged != level
+                    || *charging != battery_charging
+                    || now.duration_since(*t) >= STEADY_LOG_INTERVAL
+            }) {
+                info!("Current battery: {reading}");
+                last_logged = Some((level.clone(), battery_charging, now));
+            } else {
+                debug!("Current battery: {reading}");
+            }
+            if let Some(latest) = &latest {
+                *latest.lock().unwrap() = Some(Reading {
+                    level: level.clone(),
+                    charging: battery_charging,
+                    at: SystemTime::now(),
+                });
+            }
+            if !ready {
+                notify_systemd(SystemdState::Ready);
+                ready = true;
+                if settings.notify_on_start {
+                    let remaining = if battery_charging {
+                        source.time_to_full().await
+                    } else {
+                        source.time_to_empty().await
+                    };
+                    notification_service
+                        .notify_status(level.clone(), battery_charging, remaining.unwrap_or(None))
+                        .await?;
+                }
+            }
+
+            if was_discharging == Some(false) && discharging {
+                info!("Charger unplugged!");
+                unplugged_at = Some(now);
+                notification_service
+                    .notify_on_battery(level.clone())
+                    .await?;
+            }
+            was_discharging = Some(discharging);
+            // For a while after unplugging, warnings wait unless the battery
+            // is nearly empty.
+            let in_grace = level >= GRACE_DIRE_LEVEL
+                && unplugged_at
+                    .zip(settings.discharge_grace)
+                    .is_some_and(|(at, grace)| now.duration_since(at) < grace);
+
+            if settings.notify_charging && was_charging == Some(false) && battery_charging {
+                info!("Charger plugged in!");
+                notification_service.notify_charging(level.clone()).await?;
+            }
+            was_charging = Some(battery_charging);
+
+            if let Some(temp_warn) = settings.temp_warn
+                && !snoozed
+                && let Ok(Some(celsius)) = source.temperature_c().await
+            {
+                temperature_state = match temperature_state {
+                    _ if celsius <= temp_warn => TemperatureState::Normal,
+                    TemperatureState::NotifiedHot(t)
+                        if now.duration_since(t) < settings.temp_frequency =>
+                    {
+                        temperature_state
+                    }
+                    _ => {
+                        warn!("Battery temperature is {celsius:.0}°C!");
+                        notification_service.notify_hot_battery(celsius).await?;
+                        TemperatureState::NotifiedHot(now)
+                    }
+                };
+            }
+
+            if let Some(drain_warn) = settings.drain_warn {
+                let rate = if discharging {
+                    drain.record(now, &level)
+                } else {
+                    drain.reset();
+                    None
+                };
+                match rate {
+                    Some(rate) if rate > drain_warn => fast_drain += 1,
+                    _ => fast_drain = 0,
+                }
+                if let Some(rate) = rate
+                    && fast_drain >= DRAIN_SAMPLES
+                    && !snoozed
+                    && drain_notified
+                        .is_none_or(|t| now.duration_since(t) >= settings.drain_frequency)
+                {
+                    warn!("Battery draining at {rate:.1}% per minute!");
+                    notification_service.notify_fast_drain(rate).await?;
+                    drain_notified = Some(now);
+                }
+            }
+
+            if let Some(slow_charge_warn) = settings.slow_charge_warn {
+                let rate = if battery_charging {
+                    match source.energy_now_wh().await.unwrap_or(None) {
+                        Some(energy_wh) => charge_rate.record(now, energy_wh),
+                        None => None,
+                    }
+                } else {
+                    charge_rate.reset();
+                    None
+                };
+                match rate {
+                    Some(rate) if rate < slow_charge_warn => slow_charge += 1,
+                    _ => slow_charge = 0,
+                }
+                if let Some(rate) = rate
+                    && slow_charge >= DRAIN_SAMPLES
+                    && !snoozed
+                    && slow_charge_notified
+                        .is_none_or(|t| now.duration_since(t) >= settings.slow_charge_frequency)
+                {
+                    warn!("Battery charging at only {rate:.1} W!");
+                    notification_service.notify_slow_charge(rate).await?;
+                    slow_charge_notified = Some(now);
+                }
+            }
+
+            if let Some(limit) = &settings.charge_limit_warn {
+                if battery_charging && level >= *limit && !over_charge_limit {
+                    info!("Charged past {limit}");
+                    notification_service
+                        .notify_charge_limit(level.clone())
+                        .await?;
+                    over_charge_limit = true;
+                } else if !battery_charging && level < *limit {
+                    over_charge_limit = false;
+                }
+            }
+
+            // The level the warnings are judged at.
+            let judged = match settings.threshold_source {
+                ThresholdSource::Percent => level.clone(),
+                ThresholdSource::CapacityLevel => {
+                    let capacity_level = source.capacity_level().await.unwrap_or(None);
+                    judged_level(&settings, &level, capacity_level)
+                }
+                ThresholdSource::Minutes => {
+                    let remaining = source.time_to_empty().await.unwrap_or(None);
+                    judged_by_minutes(&settings, &level, remaining)
+                }
+            };
+
+            // Warnings stay latched, even while briefly charging, until the level
+            // recovers past their clear level, so a level hovering around a
+            // threshold doesn't trigger a fresh warning each time it dips. Clearing
+            // one warning leaves the next less severe one latched in its place.
+            if let Notified(tier, t, repeats, dismissed) = notification_state {
+                notification_state = match settings.tiers[tier..]
+                    .iter()
+                    .position(|tier| judged <= tier.clear)
+                {
+                    Some(0) => Notified(tier, t, repeats, dismissed),
+                    // The less severe warning wasn't the one repeated.
+                    Some(offset) => Notified(tier + offset, t, 0, dismissed),
+                    None => NeverNotified,
+                };
+            }
+
+            // Whether to remind that the battery is charged, while it's
+            // still plugged in.
+            let full_reminder = matches!(notification_state, Full(t)
+                if settings.full_frequency.is_some_and(|every| now.duration_since(t) >= every))
+                && level >= settings.full_level;
+
+            // Why a warning was or wasn't shown, for --verbose.
+            let decision = if battery_charging {
+                // Warnings left on screen are moot once plugged in.
+                if let Err(e) = notification_service.close_alerts().await {
+                    warn!("Failed to close battery warnings: {e}");
+                }
+                // Past the charged level, counting down to 100% is moot.
+                if level < settings.full_level
+                    && let Some(until_full) = source.time_to_full().await.unwrap_or(None)
+                {
+                    info!("Charging, full in {}", format_estimate(until_full));
+                }
+                if level >= settings.full_level
+                    && (matches!(notification_state, Charging) || full_reminder)
+                {
+                    info!("Battery charged!");
+                    notification_service
+                        .notify_full_charge(level.clone(), &settings.full_icon)
+                        .await?;
+                    notification_state = Full(now);
+                    "charged".to_string()
+                } else {
+                    if matches!(notification_state, NeverNotified | Suspended) {
+                        notification_state = Charging
+                    }
+                    "charging".to_string()
+                }
+            } else if full_reminder && charging_state != ChargingState::Discharging {
+                info!("Battery still charged and plugged in");
+                notification_service
+                    .notify_full_charge(level.clone(), &settings.full_icon)
+                    .await?;
+                notification_state = Full(now);
+                "reminded charged".to_string()
+            } else if matches!(
+                charging_state,
+                ChargingState::Full | ChargingState::NotCharging
+            ) {
+                // Plugged in and not draining, however low it reads, so don't
+                // warn.
+                "plugged in and not draining".to_string()
+            } else if matches!(notification_state, Full(_)) && level < settings.full_level {
+                notification_state = NeverNotified;
+                "discharging since charged".to_string()
+            } else if notification_state == Suspended {
+                // Even if it's woken up still low, it only suspends once
+                // per discharge, and warnings would be moot.
+                "suspended already".to_string()
+            } else if let Some(suspend_at) = &settings.suspend_at
+                && level <= *suspend_at
+            {
+                if settings.suspend_warning {
+                    warn!("Suspending in {} seconds!", SUSPEND_WARNING.as_secs());
+                    notification_service
+                        .notify_suspend(level.clone(), SUSPEND_WARNING)
+                        .await?;
+                    sleep_with_watchdog(SUSPEND_WARNING, watchdog).await;
+                }
+                if settings.suspend_warning && source.charging().await.unwrap_or(false) {
+                    info!("Charging, not suspending");
+                    notification_state = Charging;
+                    "plugged in before suspending".to_string()
+                } else {
+                    error!("Battery at {level}, suspending!");
+                    // The lock would block our own suspend too.
+                    inhibitor.release().await;
+                    if settings.dry_run {
+                        log_shell(&settings.suspend_cmd, &level);
+                    } else {
+                        spawn_shell(&settings.suspend_cmd, &level);
+                    }
+                    notification_state = Suspended;
+                    format!("suspended at {suspend_at}")
+                }
+            } else if !snoozed
+                && !in_grace
+                && let Some((index, tier)) =
+                    settings.tiers.iter().enumerate().find(|(index, tier)| {
+                        // A recent warning at this tier or a more severe one holds
+                        // this one back until its frequency has passed, and
+                        // any time it was dismissed for.
+                        judged <= tier.level
+                            && !held_quiet(tier)
+                            && !matches!(notification_state,
+                            Notified(notified, t, _, dismissed) if notified <= *index
+                                && (now.duration_since(t) < tier.frequency_at(&judged)
+                                    || dismissed.is_some_and(|until| now < until)))
+                    })
+            {
+                if tier.urgency == Urgency::Critical {
+                    error!("{}", tier.summary);
+                } else {
+                    warn!("{}", tier.summary);
+                }
+                let details = WarningDetails {
+                    energy_wh: if settings.show_energy {
+                        source.energy_now_wh().await.unwrap_or(None)
+                    } else {
+                        None
+                    },
+                    remaining: source.time_to_empty().await.unwrap_or(None),
+                    watts,
+                    // Only read for the messages that show it.
+                    health_percent: if tier.message.contains("{health}") {
+                        source.health_percent().await.unwrap_or(None)
+                    } else {
+                        None
+                    },
+                };
+                let repeats = match notification_state {
+                    Notified(notified, _, repeats, _) if notified == index => repeats + 1,
+                    _ => 0,
+                };
+                if notification_service
+                    .notify_warning(tier, level.clone(), &details, repeats)
+                    .await?
+                {
+                    for command in &tier.commands {
+                        if settings.dry_run {
+                            log_shell(command, &level);
+                        } else {
+                            spawn_shell(command, &level);
+                        }
+                    }
+                    if settings.inhibit_on_critical
+                        && tier.urgency == Urgency::Critical
+                        && !settings.dry_run
+                        && let Err(e) = inhibitor.take(&tier.summary)
+                    {
+                        warn!("Failed to inhibit suspend: {e:#}");
+                    }
+                    notification_state = Notified(index, now, repeats, None);
+                    session.warned(tier.urgency);
+                    format!("warned at {}", tier.level)
+                } else {
+                    // Warned of again on the next poll, once the gap allows.
+                    "held back by --max-notify-rate".to_string()
+                }
+            } else if snoozed {
+                "snoozed".to_string()
+            } else if in_grace {
+                "just unplugged".to_string()
+            } else if let Some(tier) = settings.tiers.iter().find(|tier| judged <= tier.level) {
+                match notification_state {
+                    _ if held_quiet(tier) => "quiet hours".to_string(),
+                    Notified(_, _, _, Some(until)) if now < until => format!(
+                        "dismissed for another {}s",
+                        until.duration_since(now).as_secs()
+                    ),
+                    Notified(_, t, _, _) => format!(
+                        "warned {}s ago, repeating after {}s",
+                        now.duration_since(t).as_secs(),
+                        tier.frequency_at(&judged).as_secs()
+                    ),
+                    _ => "warned already".to_string(),
+                }
+            } else {
+                "above every warning level".to_string()
+            };
+            let decision = format!(
+                "{level}, {charging_state:?}, {previous_state:?} -> {notification_state:?}: \
+                 {decision}"
+            );
+            if settings.verbose {
+                info!("Decision: {decision}");
+            } else {
+                debug!("Decision: {decision}");
+            }
+
+            if let Some(step) = settings.step_notify
+                && !snoozed
+            {
+                // Levels from 71% to 80% are in the 80% step for a step of 10.
+                let current_step = level.level().div_ceil(step);
+                if battery_charging {
+                    last_step = None;
+                } else {
+                    // Don't repeat a warning that was shown for this reading.
+                    let warned = matches!(notification_state, Notified(_, t, ..) if t == now);
+                    // Steps passed in quiet hours are still counted, so none
+                    // is shown late once they end.
+                    if last_step.is_some_and(|last_step| current_step < last_step)
+                        && !warned
+                        && !quiet
+                    {
+                        info!("Battery down to {level}");
+                        notification_service.notify_step(level.clone()).await?;
+                    }
+                    last_step = Some(
+                        last_step.map_or(current_step, |last_step: u8| last_step.min(current_step)),
+                    );
+                }
+            }
+
+            // After the warnings, so they're shown ahead of anything held back.
+            notification_service.show_held().await?;
+
+            if settings.watch {
+                watch::draw(&watch::Snapshot {
+                    level: &level,
+                    charging_state,
+                    watts,
+                    time_to_empty: source.time_to_empty().await.unwrap_or(None),
+                    time_to_full: source.time_to_full().await.unwrap_or(None),
+                    notification_state: describe_state(notification_state, &level, &settings, now),
+                    snoozed,
+                    quiet,
+                    notifications: notification_service.sent(),
+                    next_poll: poll_interval,
+                });
+            }
+
+            if let Some(metrics) = &metrics {
+                let mut metrics = metrics.lock().unwrap();
+                metrics.level = Some(level.level());
+                metrics.charging = Some(battery_charging);
+                metrics.power_watts = watts;
+                metrics.notifications = notification_service.sent();
+            }
+
+            // Hold the lock for as long as a critical warning is latched and
+            // the charger is out.
+            let critical = matches!(notification_state,
+                Notified(tier, ..) if settings.tiers[tier].urgency == Urgency::Critical);
+            if !(critical && discharging && settings.inhibit_on_critical) {
+                inhibitor.release().await;
+            }
+
+            if notification_state != previous_state {
+                debug!("Notification state {previous_state:?} -> {notification_state:?}");
+                save_state(state_path.as_deref(), notification_state, &settings.tiers);
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let (result, stopped) = tokio::select! {
+        result = monitor => (result, false),
+        _ = &mut poller => (Ok(()), false),
+        () = shutdown => {
+            info!("Shutting down");
+            notify_systemd(SystemdState::Stopping);
+            (Ok(()), true)
+        }
+    };
+    poller.abort();
+    if stopped {
+        session.log();
     }
+    inhibitor.release().await;
+    if let Err(e) = notification_service.close_alerts().await {
+        warn!("Failed to close battery warnings: {e}");
+    }
+    result
 }
 
-impl Drop for NotificationService {
-    fn drop(&mut self) {
-        libnotify::uninit();
+/// Saves `state` to `path`, if there is one, only warning if it can't be.
+fn save_state(path: Option<&Path>, state: NotificationState, tiers: &[Tier]) {
+    if let Some(path) = path
+        && let Err(e) = state::save(path, state, tiers)
+    {
+        warn!("Failed to save notification state: {e:#}");
     }
 }
 
-#[derive(Clone, PartialOrd, Ord, Eq, PartialEq)]
-struct BatteryLevel(u8);
+/// A reading taken by [`poll_battery`], with how long until the next one.
+struct Poll {
+    reading: Result<Option<BatterySnapshot>, anyhow::Error>,
+    next: Duration,
+    /// Whether this is the first reading since the system resumed from
+    /// suspend.
+    resumed: bool,
+}
 
-impl BatteryLevel {
-    #[requires(percent.clone().try_into().is_ok_and(|v| v <= 100))]
-    #[ensures(ret.0 <= 100)]
-    fn new<P: TryInto<u8, Error = E> + Clone, E: Debug>(percent: P) -> Self {
-        let level = percent.try_into().expect("could not convert to u8");
-        BatteryLevel(level)
+/// Sends each reading from `stream` to `polls`, waiting between them as
+/// `intervals` says for the level, which polls more often as the battery
+/// drains unless configured not to. A failed poll is retried sooner, backing
+/// off so a lasting failure doesn't hammer sysfs. A resume from suspend is
+/// read straight away. A reading is dropped if the last one is still waiting
+/// to be handled, e.g. behind a hung notification server, rather than holding
+/// up the watchdog's pings. Returns once `polls` is closed.
+async fn poll_battery(
+    mut stream: LocalBoxStream<'_, Result<Option<BatterySnapshot>, anyhow::Error>>,
+    intervals: &RefCell<PollInterval>,
+    watchdog: Option<Duration>,
+    polls: mpsc::Sender<Poll>,
+) {
+    let mut failures: u32 = 0;
+    let mut resumed = false;
+    while let Some(reading) = stream.next().await {
+        let next = {
+            let intervals = intervals.borrow();
+            match &reading {
+                Ok(Some(snapshot)) => {
+                    failures = 0;
+                    intervals.interval(Some(&snapshot.level))
+                }
+                Ok(None) => intervals.interval(None),
+                Err(_) => {
+                    failures += 1;
+                    RETRY_BACKOFF
+                        .saturating_mul(1 << (failures - 1).min(16))
+                        .min(intervals.interval(None))
+                }
+            }
+        };
+        let poll = Poll {
+            reading,
+            next,
+            resumed,
+        };
+        match polls.try_send(poll) {
+            Ok(()) => resumed = false,
+            // Kept for the next reading that gets through.
+            Err(TrySendError::Full(_)) => {
+                debug!("Dropped a reading, the last is still being handled")
+            }
+            Err(TrySendError::Closed(_)) => return,
+        }
+        if sleep_until_poll(next, watchdog).await {
+            resumed = true;
+            sleep(RESUME_SETTLE).await;
+        }
     }
+}
 
-    #[ensures(ret <= 100)]
-    #[ensures(ret == self.0)]
-    fn level(&self) -> u8 {
-        self.0
+/// `level` moved into the band the firmware's `capacity_level` puts it in, so
+/// e.g. `Low` gives the low warning whatever the percentage, or as it is if
+/// the firmware doesn't say.
+fn judged_level(
+    settings: &Settings,
+    level: &BatteryLevel,
+    capacity_level: Option<CapacityLevel>,
+) -> BatteryLevel {
+    let above = |threshold: &BatteryLevel| BatteryLevel::saturating(threshold.level() + 1);
+    match capacity_level {
+        None => level.clone(),
+        Some(CapacityLevel::Critical) => level.min(&settings.critical_level).clone(),
+        Some(CapacityLevel::Low) => level
+            .min(&settings.low_level)
+            .max(&above(&settings.critical_level))
+            .clone(),
+        Some(CapacityLevel::Normal | CapacityLevel::High | CapacityLevel::Full) => {
+            level.max(&above(&settings.low_level)).clone()
+        }
     }
 }
 
-impl Display for BatteryLevel {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}%", self.0)
+/// `level` moved to the critical level or just above it, by whether the
+/// `remaining` runtime is within `--critical-minutes`, or as it is if that
+/// isn't known.
+fn judged_by_minutes(
+    settings: &Settings,
+    level: &BatteryLevel,
+    remaining: Option<Duration>,
+) -> BatteryLevel {
+    match remaining {
+        None => level.clone(),
+        Some(remaining) if remaining <= settings.critical_minutes => {
+            level.min(&settings.critical_level).clone()
+        }
+        Some(_) => level
+            .max(&BatteryLevel::saturating(
+                settings.critical_level.level() + 1,
+            ))
+            .clone(),
     }
 }
 
-fn calc_battery_level(current: f32, total: f32) -> BatteryLevel {
-    let level: i32 = (((current / total) * 100.0).round() as i32).min(100);
-    BatteryLevel::new(level)
+/// Waits for a client of the socket to ask for another profile, or forever
+/// without a socket.
+async fn next_switch(switches: &mut Option<socket::ProfileSwitches>) -> Option<String> {
+    match switches {
+        Some(switches) => switches.recv().await,
+        None => std::future::pending().await,
+    }
 }
 
-fn battery_level_stream() -> BoxStream<'static, BatteryLevel> {
-    Box::pin(stream! {
-        let total = battery_energy_full().await
-            .expect("Failed to get full battery level");
-
-        loop {
-            let current = battery_energy_now().await
-                .expect("Failed to get current battery level");
+/// Re-reads the settings from the command line and config file, keeping the
+/// current ones if that fails, using the config's `profile` over the command
+/// line's if given. Returns `state` for the new settings, keeping a warning
+/// if there's still a tier at its level.
+fn reload_settings(
+    settings: &mut Settings,
+    state: NotificationState,
+    profile: Option<String>,
+) -> NotificationState {
+    let new = match Args::try_parse()
+        .map(|args| args.with_profile(profile))
+        .map_err(anyhow::Error::from)
+        .and_then(Settings::load)
+    {
+        Ok(new) => new,
+        Err(e) => {
+            warn!("Failed to reload the config, keeping the current settings: {e:#}");
+            return state;
+        }
+    };
+    let changes = settings.changes(&new);
+    if changes.is_empty() {
+        info!("Reloaded the config, nothing changed");
+    }
+    for change in changes {
+        info!("Reloaded {change}");
+    }
 
-            yield calc_battery_level(current, total);
+    let state = match state {
+        NotificationState::Notified(tier, t, repeats, dismissed) => {
+            match new
+                .tiers
+                .iter()
+                .position(|new_tier| new_tier.level == settings.tiers[tier].level)
+            {
+                Some(tier) => NotificationState::Notified(tier, t, repeats, dismissed),
+                None => NotificationState::NeverNotified,
+            }
         }
-    })
+        state => state,
+    };
+    *settings = new;
+    state
 }
 
-enum NotificationState {
-    NotifiedLow(Instant),
-    NotifiedCritical(Instant),
-    Charging,
-    NeverNotified,
+async fn print_health<S: BatterySource>(source: &S) -> Result<(), anyhow::Error> {
+    match source.health_percent().await? {
+        Some(health) => println!("Battery health: {health:.0}%"),
+        None => bail!("The battery doesn't report its design capacity"),
+    }
+    Ok(())
 }
 
-async fn battery_notifier() -> Result<(), anyhow::Error> {
-    use NotificationState::*;
+async fn print_cycles<S: BatterySource>(source: &S) -> Result<(), anyhow::Error> {
+    match source.cycle_count().await? {
+        Some(cycles) => println!("Battery cycle count: {cycles}"),
+        None => bail!("The battery doesn't report its cycle count"),
+    }
+    Ok(())
+}
+
+/// Shows the notification for `--test-notify` once through `notifier`,
+/// failing if it can't be shown.
+async fn test_notify<N: Notifier>(notifier: N, settings: &Settings) -> Result<(), anyhow::Error> {
+    let mut notification_service = NotificationService::new(
+        notifier,
+        settings.graded_icons,
+        settings.progress_hint,
+        settings.messages.clone(),
+        // A test notification is always shown.
+        None,
+    );
+    match &settings.mode {
+        Mode::TestWarning(level) => {
+            let tier = settings
+                .tiers
+                .iter()
+                .find(|tier| tier.level == *level)
+                .expect("the low and critical levels are tiers");
+            notification_service
+                .notify_warning(tier, level.clone(), &WarningDetails::default(), 0)
+                .await
+                .map(|_| ())
+        }
+        _ => {
+            notification_service
+                .notify_full_charge(settings.full_level.clone(), &settings.full_icon)
+                .await
+        }
+    }
+}
+
+/// Polls the battery until it is at or below `threshold` while discharging,
+/// without notifying. A failed poll is only logged, so a script waiting on
+/// this isn't released early.
+async fn wait_until<S: BatterySource>(
+    source: &S,
+    threshold: &BatteryLevel,
+    settings: &Settings,
+) -> Result<(), anyhow::Error> {
+    let mut snapshots = smoothed_levels(battery_snapshot_stream(source), settings.smooth);
+    while let Some(reading) = snapshots.next().await {
+        let level = match reading {
+            Ok(Some(snapshot)) if snapshot.discharging && snapshot.level <= *threshold => {
+                info!("Battery at {}", snapshot.level);
+                return Ok(());
+            }
+            Ok(Some(snapshot)) => Some(snapshot.level),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Skipping poll: {e:#}");
+                None
+            }
+        };
+        sleep(settings.poll_interval.interval(level.as_ref())).await;
+    }
+    Ok(())
+}
 
-    let notification_service: NotificationService = NotificationService::new(APP_NAME)?;
-
-    let mut battery_stream = battery_level_stream();
-    let mut notification_state = NeverNotified;
-    let crit_frequency = Duration::from_secs(60);
-    let low_frequency = Duration::from_secs(5 * 60);
-
-    while let Some(level) = battery_stream.next().await {
-        println!("Current battery: {level}");
-        let now = Instant::now();
-        let battery_charging = battery_charging().await?;
-
-        if battery_charging {
-            notification_state = Charging
-        } else if !battery_charging
-            && level <= CRITICAL_BATTERY_LEVEL
-            && !matches!(notification_state,
-                 NotifiedCritical(t) if now.duration_since(t) < crit_frequency)
-        {
-            println!("Battery critical!");
-            notification_service.notify_critical_battery(level)?;
-            notification_state = NotifiedCritical(now)
-        } else if !battery_charging
-            && level <= LOW_BATTERY_LEVEL
-            && !matches!(notification_state,
-                NotifiedLow(t) if now.duration_since(t) < low_frequency)
-            && !matches!(notification_state,
-                NotifiedCritical(t) if now.duration_since(t) < low_frequency)
-        {
-            println!("Battery low!");
-            notification_service.notify_low_battery(level)?;
-            notification_state = NotifiedLow(now)
-        }
-
-        sleep(Duration::from_secs(60)).await;
+/// Sets the charge thresholds of each of `names` under `root` that is
+/// present.
+async fn set_charge_limit(
+    root: &Path,
+    names: &[String],
+    start: &BatteryLevel,
+    end: &BatteryLevel,
+) -> Result<(), anyhow::Error> {
+    let batteries: Vec<_> = names
+        .iter()
+        .map(|name| (name, Battery::at(root.join(name))))
+        .filter(|(_, battery)| battery.is_present())
+        .collect();
+    if batteries.is_empty() {
+        return Err(anyhow!("No battery found among {}", names.join(", ")))
+            .context(Failure::NoBattery);
+    }
+    for (name, battery) in &batteries {
+        set_charge_thresholds(battery, start, end).await?;
+        info!("{name} now charges from {start} to {end}");
     }
+    Ok(())
+}
+
+/// What `--status` prints, as JSON.
+#[derive(Serialize)]
+struct Status {
+    level: u8,
+    charging: bool,
+    energy_wh: Option<f32>,
+    power_draw_watts: Option<f32>,
+    time_to_empty_secs: Option<u64>,
+    time_to_full_secs: Option<u64>,
+    manufacturer: Option<String>,
+    model_name: Option<String>,
+    technology: Option<String>,
+    capacity_level: Option<String>,
+    /// Only with `--verbose`, and `null` there if unknown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    voltage_v: Option<Option<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current_a: Option<Option<f32>>,
+}
+
+/// `value` to `places` decimal places, so the JSON isn't cluttered with
+/// digits no reading is that precise to.
+fn rounded(value: f32, places: i32) -> f32 {
+    let scale = 10f32.powi(places);
+    (value * scale).round() / scale
+}
 
+/// Prints the status as JSON, with `verbose` adding the voltage and current.
+async fn print_status<S: BatterySource>(source: &S, verbose: bool) -> Result<(), anyhow::Error> {
+    let snapshot = battery_snapshot_stream(source)
+        .next()
+        .await
+        .ok_or_else(|| anyhow!("No battery reading"))??
+        .ok_or_else(|| anyhow!("No battery present"))
+        .context(Failure::NoBattery)?;
+    let info = source.info().await.unwrap_or(None).unwrap_or_default();
+    let (voltage_v, current_a) = if verbose {
+        let volts = source.voltage().await.unwrap_or(None);
+        let amps = source.current().await.unwrap_or(None);
+        (
+            Some(volts.map(|volts| rounded(volts, 3))),
+            Some(amps.map(|amps| rounded(amps, 3))),
+        )
+    } else {
+        (None, None)
+    };
+    let status = Status {
+        level: snapshot.level.level(),
+        charging: snapshot.charging(),
+        energy_wh: source
+            .energy_now_wh()
+            .await
+            .unwrap_or(None)
+            .map(|wh| rounded(wh, 2)),
+        power_draw_watts: snapshot.power_draw_watts.map(|watts| rounded(watts, 2)),
+        time_to_empty_secs: source
+            .time_to_empty()
+            .await
+            .unwrap_or(None)
+            .map(|estimate| estimate.as_secs()),
+        time_to_full_secs: source
+            .time_to_full()
+            .await
+            .unwrap_or(None)
+            .map(|estimate| estimate.as_secs()),
+        manufacturer: info.manufacturer,
+        model_name: info.model_name,
+        technology: info.technology,
+        capacity_level: source
+            .capacity_level()
+            .await
+            .unwrap_or(None)
+            .map(|level| format!("{level:?}")),
+        voltage_v,
+        current_a,
+    };
+    println!("{}", serde_json::to_string(&status)?);
     Ok(())
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), anyhow::Error> {
-    battery_notifier().await?;
+fn main() {
+    if let Err(e) = try_main() {
+        eprintln!("Error: {e:?}");
+        std::process::exit(exit::code(&e));
+    }
+}
+
+fn try_main() -> Result<(), anyhow::Error> {
+    let args = Args::try_parse().unwrap_or_else(|e| {
+        // A bad flag is a config error too, rather than clap's own exit code.
+        if e.use_stderr() {
+            let _ = e.print();
+            std::process::exit(Failure::Config.code());
+        }
+        e.exit()
+    });
+    let default_filter = if args.quiet_logs() { "off" } else { "info" };
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter)),
+        )
+        .with_ansi(!args.logs_to_file());
+    if args.logs_to_stderr() {
+        logs.with_writer(std::io::stderr).init();
+    } else {
+        logs.init();
+    }
+
+    let settings = Settings::load(args).context(Failure::Config)?;
+    // Before the runtime starts any threads, which the child wouldn't keep.
+    if let Some(daemon) = &settings.daemon {
+        daemon::daemonize(daemon)?;
+    }
+    let pidfile = settings
+        .daemon
+        .as_ref()
+        .and_then(|daemon| daemon.pidfile.clone());
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    // The poller is spawned onto it, as the battery sources aren't `Send`.
+    let result = tokio::task::LocalSet::new().block_on(&runtime, start(settings));
+    if let Some(pidfile) = pidfile
+        && let Err(e) = std::fs::remove_file(&pidfile)
+    {
+        warn!("Failed to remove {}: {e}", pidfile.display());
+    }
+    result
+}
+
+/// Carries out `settings.mode`.
+async fn start(settings: Settings) -> Result<(), anyhow::Error> {
+    // These don't need a battery.
+    match settings.mode {
+        Mode::Install => return install::install(),
+        Mode::Uninstall => return install::uninstall(),
+        Mode::SetChargeLimit(start, end) => {
+            return set_charge_limit(&settings.sysfs_root, &settings.batteries, &start, &end).await;
+        }
+        Mode::TestWarning(_) | Mode::TestFull => {
+            if settings.dry_run {
+                return test_notify(DryRunNotifier, &settings).await;
+            }
+            return match settings.backend {
+                #[cfg(feature = "libnotify")]
+                Backend::Libnotify => {
+                    let notifier = LibnotifyNotifier::new(APP_NAME).context(Failure::Notifier)?;
+                    test_notify(notifier, &settings).await
+                }
+                Backend::Dbus => {
+                    let notifier = DbusNotifier::connect(APP_NAME)
+                        .await
+                        .context(Failure::Notifier)?;
+                    test_notify(notifier, &settings).await
+                }
+            };
+        }
+        _ => {}
+    }
+    match settings.source {
+        Source::Sysfs => {
+            let source = SysfsSource::new(
+                &settings.sysfs_root,
+                &settings.batteries,
+                settings.capacity_source,
+                settings.charge_source,
+                settings.unknown_status,
+            )
+            .context(Failure::NoBattery)?;
+            run(source, settings).await
+        }
+        Source::Upower => {
+            let source = UPowerSource::new().await.context(Failure::NoBattery)?;
+            run(source, settings).await
+        }
+    }
+}
+
+/// Runs the modes that read the batteries from `source`.
+async fn run<S: BatterySource + 'static>(
+    source: S,
+    settings: Settings,
+) -> Result<(), anyhow::Error> {
+    if let Mode::Until(threshold) = &settings.mode {
+        return wait_until(&source, threshold, &settings).await;
+    }
+    match settings.mode {
+        Mode::Monitor if settings.dry_run || settings.watch => {
+            battery_notifier(source, DryRunNotifier, settings).await?
+        }
+        Mode::Monitor => match settings.backend {
+            #[cfg(feature = "libnotify")]
+            Backend::Libnotify => {
+                let notifier = with_fallbacks(
+                    FallbackNotifier::new(APP_NAME).await,
+                    DbusNotifier::new(APP_NAME),
+                    settings.fallback_cmd.clone(),
+                );
+                battery_notifier(source, notifier, settings).await?
+            }
+            Backend::Dbus => {
+                let notifier = DbusNotifier::new(APP_NAME);
+                let notifier = CommandFallback::new(notifier, settings.fallback_cmd.clone());
+                battery_notifier(source, notifier, settings).await?
+            }
+        },
+        Mode::Health => print_health(&source).await?,
+        Mode::Status => print_status(&source, settings.verbose).await?,
+        Mode::Cycles => print_cycles(&source).await?,
+        Mode::JsonEvents => json_events::print_events(&source, &settings).await?,
+        Mode::Until(_)
+        | Mode::TestWarning(_)
+        | Mode::TestFull
+        | Mode::Install
+        | Mode::Uninstall
+        | Mode::SetChargeLimit(..) => {
+            unreachable!()
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use ChargingState::{Charging, Discharging, Full};
+    use battery_notifier::battery::MockSource;
+
+    use super::*;
+    use crate::notification::Message;
+
+    /// Records the summary of each notification shown.
+    #[derive(Clone, Default)]
+    struct Shown(Rc<RefCell<Vec<String>>>);
+
+    impl Notifier for Shown {
+        type Handle = ();
+
+        async fn show(&self, message: &Message, _: Option<&()>) -> Result<(), anyhow::Error> {
+            self.0.borrow_mut().push(message.summary.clone());
+            Ok(())
+        }
+
+        async fn close(&self, _: &()) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+    }
+
+    /// Like [`Shown`], but picks the action on the first warning once it's
+    /// shown.
+    struct Picking(Shown, Action, std::cell::Cell<bool>);
+
+    impl Notifier for Picking {
+        type Handle = ();
+
+        async fn show(&self, message: &Message, _: Option<&()>) -> Result<(), anyhow::Error> {
+            self.0.show(message, None).await
+        }
+
+        async fn close(&self, _: &()) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+
+        async fn action(&self) -> Action {
+            while self.2.get() || self.0.0.borrow().is_empty() {
+                sleep(Duration::from_secs(1)).await;
+            }
+            self.2.set(true);
+            self.1
+        }
+    }
+
+    /// The settings for `args`, polling every minute and stopping at the
+    /// first failed poll. A dry run, so nothing is saved or run.
+    fn settings(args: &[&str]) -> Settings {
+        let args = [
+            APP_NAME,
+            "--dry-run",
+            "--config",
+            "/nonexistent/config.toml",
+            "--poll-interval",
+            "60",
+            "--max-failures",
+            "1",
+        ]
+        .into_iter()
+        .chain(args.iter().copied());
+        Settings::load(Args::try_parse_from(args).unwrap()).unwrap()
+    }
+
+    /// The summaries of the notifications shown for `readings`, one a
+    /// minute, by the monitor with `settings`.
+    async fn shown(
+        settings: Settings,
+        readings: impl IntoIterator<Item = (u8, ChargingState)>,
+    ) -> Vec<String> {
+        let shown = Shown::default();
+        let result = tokio::task::LocalSet::new()
+            .run_until(battery_notifier(
+                MockSource::new(readings),
+                shown.clone(),
+                settings,
+            ))
+            .await;
+        // Only once the readings run out.
+        assert!(result.is_err());
+        shown.0.take()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warning_stays_latched_until_past_its_clear_level() {
+        let settings = settings(&[]);
+        let low = settings.messages.low_summary.clone();
+        let readings = [
+            (14, Discharging),
+            // Below the clear level, and the warning isn't due again yet.
+            (18, Discharging),
+            (14, Discharging),
+            // Cleared, so the next dip warns afresh.
+            (25, Discharging),
+            (14, Discharging),
+        ];
+        assert_eq!(shown(settings, readings).await, [low.clone(), low]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warning_repeats_escalate_once_ignored() {
+        let settings = settings(&["--crit-every", "60"]);
+        let critical = settings.messages.critical_summary.clone();
+        let ignored = settings.messages.ignored_critical_summary.clone();
+        let shown = shown(settings, [(5, Discharging); 6]).await;
+        assert_eq!(
+            shown,
+            [
+                critical.clone(),
+                critical.clone(),
+                critical,
+                ignored.clone(),
+                ignored.clone(),
+                ignored,
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn less_severe_warning_repeats_without_escalating() {
+        let settings = settings(&["--crit-every", "60", "--low-every", "60"]);
+        let critical = settings.messages.critical_summary.clone();
+        let low = settings.messages.low_summary.clone();
+        let readings = [
+            (5, Discharging),
+            (5, Discharging),
+            // Past the critical warning's clear level, so the low one takes
+            // its place, counting its repeats afresh.
+            (12, Discharging),
+            (12, Discharging),
+        ];
+        assert_eq!(
+            shown(settings, readings).await,
+            [critical.clone(), critical, low.clone(), low]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warned_then_charging_then_full() {
+        let settings = settings(&[]);
+        let messages = settings.messages.clone();
+        let readings = [
+            (10, Discharging),
+            (50, Charging),
+            (100, Charging),
+            // Staying full doesn't notify again.
+            (100, Full),
+            (99, Discharging),
+            (14, Discharging),
+        ];
+        assert_eq!(
+            shown(settings, readings).await,
+            [
+                messages.low_summary.clone(),
+                messages.full_summary,
+                messages.unplugged_summary,
+                messages.low_summary,
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn starting_below_critical_warns_on_the_first_poll() {
+        let settings = settings(&[]);
+        let critical = settings.messages.critical_summary.clone();
+        assert_eq!(shown(settings, [(4, Discharging)]).await, [critical]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn notify_on_start_shows_the_status_before_warning() {
+        let settings = settings(&["--notify-on-start"]);
+        let messages = settings.messages.clone();
+        assert_eq!(
+            shown(settings, [(4, Discharging)]).await,
+            [messages.status_summary, messages.critical_summary]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warning_held_back_by_the_rate_limit_is_shown_later() {
+        let args = ["--step-notify", "10", "--max-notify-rate", "120"];
+        let step = settings(&args).messages.step_summary.clone();
+        let low = settings(&args).messages.low_summary.clone();
+        // The step to 19% is shown a minute before the low warning is due.
+        let readings = [(25, Discharging), (19, Discharging), (14, Discharging)];
+        assert_eq!(shown(settings(&args), readings).await, vec![step.clone()]);
+        let readings = [
+            (25, Discharging),
+            (19, Discharging),
+            (14, Discharging),
+            (14, Discharging),
+        ];
+        assert_eq!(shown(settings(&args), readings).await, [step, low]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn critical_warning_isnt_held_back_by_the_rate_limit() {
+        let settings = settings(&["--step-notify", "10", "--max-notify-rate", "120"]);
+        let step = settings.messages.step_summary.clone();
+        let critical = settings.messages.critical_summary.clone();
+        let readings = [(25, Discharging), (19, Discharging), (5, Discharging)];
+        assert_eq!(shown(settings, readings).await, [step, critical]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn suspends_once_per_discharge() {
+        let settings = settings(&["--suspend-at", "3", "--suspend-warning"]);
+        let messages = settings.messages.clone();
+        let readings = [
+            (3, Discharging),
+            // Neither suspends again nor warns.
+            (3, Discharging),
+            (2, Discharging),
+            (20, Charging),
+            (3, Discharging),
+        ];
+        assert_eq!(
+            shown(settings, readings).await,
+            [
+                messages.suspend_summary.clone(),
+                messages.unplugged_summary,
+                messages.suspend_summary,
+            ]
+        );
+    }
+
+    /// Like [`shown`], with `action` picked on the first warning.
+    async fn shown_picking(
+        settings: Settings,
+        action: Action,
+        readings: impl IntoIterator<Item = (u8, ChargingState)>,
+    ) -> Vec<String> {
+        let shown = Shown::default();
+        let notifier = Picking(shown.clone(), action, Default::default());
+        let result = tokio::task::LocalSet::new()
+            .run_until(battery_notifier(
+                MockSource::new(readings),
+                notifier,
+                settings,
+            ))
+            .await;
+        assert!(result.is_err());
+        shown.0.take()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dismissing_holds_repeats_back_for_a_while() {
+        let settings = settings(&["--crit-every", "60"]);
+        let critical = settings.messages.critical_summary.clone();
+        // A poll a minute, the last half an hour after the first.
+        let readings = [(5, Discharging); 31];
+        assert_eq!(
+            shown_picking(settings, Action::Dismiss, readings).await,
+            [critical.clone(), critical]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dismissing_doesnt_hold_back_a_more_severe_warning() {
+        let settings = settings(&["--low-every", "60"]);
+        let messages = settings.messages.clone();
+        let readings = [(14, Discharging), (14, Discharging), (5, Discharging)];
+        assert_eq!(
+            shown_picking(settings, Action::Dismiss, readings).await,
+            [messages.low_summary, messages.critical_summary]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn suspending_from_a_warning_holds_back_the_rest() {
+        let settings = settings(&["--crit-every", "60", "--suspend-at", "3"]);
+        let critical = settings.messages.critical_summary.clone();
+        // Neither the repeats nor --suspend-at follow.
+        let readings = [(5, Discharging), (5, Discharging), (2, Discharging)];
+        assert_eq!(
+            shown_picking(settings, Action::Suspend, readings).await,
+            [critical]
+        );
+    }
+}
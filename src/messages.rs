@@ -0,0 +1,188 @@
+//! The text of the notifications, which can be translated by a message
+//! catalog for the locale or overridden in the config file.
+//!
+//! Placeholders such as `{level}` are replaced with the value they name.
+
+use std::env;
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::APP_NAME;
+use crate::config::config_home;
+
+/// Every string shown in a notification, defaulting to English.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Messages {
+    pub critical_summary: String,
+    /// This and the other warning messages can use `{level}`,
+    /// `{time_remaining}`, `{watts}` and `{health}`.
+    pub critical_message: String,
+    /// The summary of a critical warning once it has repeated a few times
+    /// without the charger being plugged in.
+    pub ignored_critical_summary: String,
+    pub low_summary: String,
+    pub low_message: String,
+    /// Used by `[[tiers]]` tables that don't set their own summary or
+    /// message.
+    pub tier_summary: String,
+    pub tier_message: String,
+    /// Appended to a warning with `--show-energy`, with `{wh}`.
+    pub energy: String,
+    /// Appended to a warning when the time left is known, with `{time}`.
+    pub remaining: String,
+    /// The labels of the warnings' buttons, shown with `--backend dbus`.
+    pub suspend_action: String,
+    pub dismiss_action: String,
+    /// With `{level}` and `{seconds}`.
+    pub suspend_summary: String,
+    pub suspend_message: String,
+    /// With `{health}` in percent.
+    pub worn_summary: String,
+    pub worn_message: String,
+    /// With `{celsius}`.
+    pub hot_summary: String,
+    pub hot_message: String,
+    /// With `{rate}` in percent per minute.
+    pub drain_summary: String,
+    pub drain_message: String,
+    /// With `{watts}`, the net power going into the battery.
+    pub slow_charge_summary: String,
+    pub slow_charge_message: String,
+    pub step_summary: String,
+    pub step_message: String,
+    pub unplugged_summary: String,
+    pub unplugged_message: String,
+    pub charging_summary: String,
+    pub charging_message: String,
+    pub charge_limit_summary: String,
+    pub charge_limit_message: String,
+    pub full_summary: String,
+    pub full_message: String,
+    /// For `--notify-on-start`.
+    pub status_summary: String,
+    pub status_message: String,
+}
+
+impl Default for Messages {
+    fn default() -> Messages {
+        Messages {
+            critical_summary: "Battery Critical!".to_string(),
+            critical_message: "Battery critical at {level}".to_string(),
+            ignored_critical_summary: "Shutting Down Soon, Plug In!".to_string(),
+            low_summary: "Battery Low!".to_string(),
+            low_message: "Battery low at {level}".to_string(),
+            tier_summary: "Battery Low".to_string(),
+            tier_message: "Battery at {level}".to_string(),
+            energy: "({wh} Wh)".to_string(),
+            remaining: "({time} remaining)".to_string(),
+            suspend_action: "Suspend now".to_string(),
+            dismiss_action: "Dismiss for 30m".to_string(),
+            suspend_summary: "Suspending Soon!".to_string(),
+            suspend_message:
+                "Battery at {level}, suspending in {seconds} seconds unless plugged in".to_string(),
+            worn_summary: "Battery Worn".to_string(),
+            worn_message: "Battery holds only {health}% of its design capacity".to_string(),
+            hot_summary: "Battery Hot!".to_string(),
+            hot_message: "Battery temperature is {celsius}°C".to_string(),
+            drain_summary: "Battery Draining Fast".to_string(),
+            drain_message: "Battery is draining at {rate}% per minute".to_string(),
+            slow_charge_summary: "Charging Slowly".to_string(),
+            slow_charge_message: "Battery charging at only {watts} W, the charger may be too weak"
+                .to_string(),
+            step_summary: "Battery Draining".to_string(),
+            step_message: "Battery at {level}".to_string(),
+            unplugged_summary: "Charger Unplugged".to_string(),
+            unplugged_message: "Running on battery ({level})".to_string(),
+            charging_summary: "Charger Plugged In".to_string(),
+            charging_message: "Charging ({level})".to_string(),
+            charge_limit_summary: "Charge Limit Reached".to_string(),
+            charge_limit_message: "Battery at {level}, unplug to preserve the battery".to_string(),
+            full_summary: "Battery Charged".to_string(),
+            full_message: "Battery charged to {level}".to_string(),
+            status_summary: "Battery Status".to_string(),
+            status_message: "Battery at {level}".to_string(),
+        }
+    }
+}
+
+/// The placeholders a warning's message can use.
+pub const WARNING_PLACEHOLDERS: [&str; 4] = ["level", "time_remaining", "watts", "health"];
+
+/// The first `{name}` in `template` that isn't one of `known`, if any.
+pub fn unknown_placeholder<'a>(template: &'a str, known: &[&str]) -> Option<&'a str> {
+    template
+        .split('{')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}'))
+        .map(|(name, _)| name)
+        .find(|name| !known.contains(name))
+}
+
+/// `template` with each `{name}` in `values` replaced by its value.
+pub fn fill(template: &str, values: &[(&str, &dyn std::fmt::Display)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{name}}}"), &value.to_string())
+        })
+}
+
+/// The catalogs to try for the locale in `LC_ALL`, `LC_MESSAGES` or `LANG`,
+/// most specific first, e.g. `de_DE.toml` then `de.toml` for `de_DE.UTF-8`.
+fn catalog_paths() -> Vec<PathBuf> {
+    let Some(locale) = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|locale| !locale.is_empty())
+    else {
+        return Vec::new();
+    };
+    let Some(dir) = config_home() else {
+        return Vec::new();
+    };
+    let locale = locale.split(['.', '@']).next().unwrap_or_default();
+    if locale == "C" || locale == "POSIX" || locale.is_empty() {
+        return Vec::new();
+    }
+    let mut names = vec![locale];
+    if let Some((language, _)) = locale.split_once('_') {
+        names.push(language);
+    }
+    names
+        .into_iter()
+        .map(|name| {
+            dir.join(APP_NAME)
+                .join("messages")
+                .join(format!("{name}.toml"))
+        })
+        .collect()
+}
+
+/// The messages from the first catalog found for the locale, with the
+/// `[messages]` table from the config file on top and English for anything
+/// neither sets.
+pub fn load(overrides: Option<toml::Table>) -> Result<Messages, anyhow::Error> {
+    let mut table = toml::Table::new();
+    for path in catalog_paths() {
+        match read_to_string(&path) {
+            Ok(raw) => {
+                table = toml::from_str(&raw)
+                    .with_context(|| format!("Invalid message catalog {}", path.display()))?;
+                break;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        }
+    }
+    table.extend(overrides.unwrap_or_default());
+    toml::Value::Table(table)
+        .try_into()
+        .context("Invalid messages")
+}
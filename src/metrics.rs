@@ -0,0 +1,133 @@
+//! An HTTP endpoint serving the latest reading in Prometheus' text format,
+//! for dashboards. It only answers `GET /metrics`, so a full HTTP server is
+//! more than it needs.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+/// How long to wait after failing to accept a connection before trying
+/// again, e.g. while out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+/// How long a client has to send its request line and headers.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// The most of a request read, headers and all, before giving up on it.
+const MAX_REQUEST_BYTES: u64 = 8192;
+
+/// The values served, as last updated by the monitor.
+#[derive(Default)]
+pub struct Metrics {
+    /// `None` before the first reading and while no battery is present.
+    pub level: Option<u8>,
+    pub charging: Option<bool>,
+    pub power_watts: Option<f32>,
+    /// Notifications shown since the daemon started.
+    pub notifications: u64,
+}
+
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+impl Metrics {
+    fn render(&self) -> String {
+        let mut text = String::new();
+        let mut gauge = |name: &str, help: &str, value: Option<String>| {
+            if let Some(value) = value {
+                text += &format!("# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n");
+            }
+        };
+        gauge(
+            "battery_level",
+            "Battery level in percent.",
+            self.level.map(|level| level.to_string()),
+        );
+        gauge(
+            "battery_charging",
+            "Whether the battery is charging.",
+            self.charging.map(|charging| (charging as u8).to_string()),
+        );
+        gauge(
+            "battery_power_watts",
+            "Power flowing into or out of the battery.",
+            self.power_watts.map(|watts| watts.to_string()),
+        );
+        text += &format!(
+            "# HELP battery_notifications_total Notifications shown.\n\
+             # TYPE battery_notifications_total counter\n\
+             battery_notifications_total {}\n",
+            self.notifications
+        );
+        text
+    }
+}
+
+/// Listens on `addr` and serves the returned [`SharedMetrics`].
+pub async fn serve(addr: SocketAddr) -> Result<SharedMetrics, anyhow::Error> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {addr}"))?;
+    let metrics = SharedMetrics::default();
+
+    let shared = metrics.clone();
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let shared = shared.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream, &shared).await {
+                            debug!("Metrics request failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!("Failed to accept a metrics connection: {e}");
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                }
+            }
+        }
+    });
+    Ok(metrics)
+}
+
+/// Answers a single request and closes the connection.
+async fn respond(stream: TcpStream, metrics: &SharedMetrics) -> Result<(), anyhow::Error> {
+    let (reader, mut writer) = stream.into_split();
+    let request = timeout(REQUEST_TIMEOUT, read_request(reader))
+        .await
+        .context("Timed out reading the request")??;
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.lock().unwrap().render()),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// The request line, once the headers after it have been read too.
+async fn read_request(reader: impl AsyncRead + Unpin) -> Result<String, anyhow::Error> {
+    let mut lines = BufReader::new(reader.take(MAX_REQUEST_BYTES)).lines();
+    let request = lines.next_line().await?.unwrap_or_default();
+    // Skip the headers; nothing in them matters here.
+    while let Some(line) = lines.next_line().await? {
+        if line.is_empty() {
+            break;
+        }
+    }
+    Ok(request)
+}
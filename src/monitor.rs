@@ -0,0 +1,276 @@
+//! A battery monitor for embedding in other programs, which polls a
+//! [`BatterySource`] and reports the battery crossing its thresholds as
+//! [`BatteryEvent`]s.
+
+use std::time::Duration;
+
+use anyhow::bail;
+use futures::StreamExt;
+use futures::stream::LocalBoxStream;
+use tokio::time::sleep;
+
+use crate::battery::{
+    BatteryLevel, BatterySnapshot, BatterySource, ChargingState, battery_snapshot_stream,
+};
+
+const LOW_LEVEL: BatteryLevel = BatteryLevel::saturating(15);
+const CRITICAL_LEVEL: BatteryLevel = BatteryLevel::saturating(6);
+const FULL_LEVEL: BatteryLevel = BatteryLevel::saturating(100);
+const INTERVAL: Duration = Duration::from_secs(60);
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Something the battery did, with its level at the time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BatteryEvent {
+    /// Discharged down to the low level.
+    LowReached(BatteryLevel),
+    /// Discharged down to the critical level.
+    CriticalReached(BatteryLevel),
+    /// Started charging.
+    Charging(BatteryLevel),
+    /// Charged up to the full level, or reported full.
+    Full(BatteryLevel),
+}
+
+impl BatteryEvent {
+    pub fn level(&self) -> &BatteryLevel {
+        match self {
+            BatteryEvent::LowReached(level)
+            | BatteryEvent::CriticalReached(level)
+            | BatteryEvent::Charging(level)
+            | BatteryEvent::Full(level) => level,
+        }
+    }
+}
+
+/// Where [`BatteryMonitor::run`] sends events, e.g. a notifier.
+#[allow(async_fn_in_trait)]
+pub trait EventSink {
+    async fn send(&mut self, event: BatteryEvent) -> Result<(), anyhow::Error>;
+}
+
+/// Configures a [`BatteryMonitor`]. Anything left unset takes the daemon's
+/// defaults, and the batteries watched are the source's.
+pub struct BatteryMonitorBuilder<'a, S> {
+    source: &'a S,
+    low: BatteryLevel,
+    critical: BatteryLevel,
+    full: BatteryLevel,
+    interval: Duration,
+}
+
+impl<'a, S: BatterySource> BatteryMonitorBuilder<'a, S> {
+    pub fn low(mut self, level: BatteryLevel) -> Self {
+        self.low = level;
+        self
+    }
+
+    pub fn critical(mut self, level: BatteryLevel) -> Self {
+        self.critical = level;
+        self
+    }
+
+    /// The level a charging battery counts as full at.
+    pub fn full(mut self, level: BatteryLevel) -> Self {
+        self.full = level;
+        self
+    }
+
+    /// How long to wait between polls, at least a second so that a zero
+    /// interval doesn't poll in a busy loop.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(MIN_INTERVAL);
+        self
+    }
+
+    /// Fails unless the critical level is below the low level.
+    pub fn build(self) -> Result<BatteryMonitor<'a>, anyhow::Error> {
+        if self.critical >= self.low {
+            bail!(
+                "The critical level ({}) must be below the low level ({})",
+                self.critical,
+                self.low
+            );
+        }
+        Ok(BatteryMonitor {
+            snapshots: battery_snapshot_stream(self.source),
+            low: self.low,
+            critical: self.critical,
+            full: self.full,
+            interval: self.interval,
+            polled: false,
+            warned: None,
+            charging: None,
+            full_reported: false,
+        })
+    }
+}
+
+/// The warning last given while discharging.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Warned {
+    Low,
+    Critical,
+}
+
+/// Polls a battery and reports what it does, either to an [`EventSink`]
+/// with [`run`](Self::run) or one event at a time with
+/// [`next_event`](Self::next_event).
+pub struct BatteryMonitor<'a> {
+    snapshots: LocalBoxStream<'a, Result<Option<BatterySnapshot>, anyhow::Error>>,
+    low: BatteryLevel,
+    critical: BatteryLevel,
+    full: BatteryLevel,
+    interval: Duration,
+    /// Whether the battery was polled yet, so the first poll isn't delayed.
+    polled: bool,
+    warned: Option<Warned>,
+    /// Whether the last reading was charging, `None` before the first and
+    /// while no battery is present.
+    charging: Option<bool>,
+    full_reported: bool,
+}
+
+impl<'a> BatteryMonitor<'a> {
+    pub fn builder<S: BatterySource>(source: &'a S) -> BatteryMonitorBuilder<'a, S> {
+        BatteryMonitorBuilder {
+            source,
+            low: LOW_LEVEL,
+            critical: CRITICAL_LEVEL,
+            full: FULL_LEVEL,
+            interval: INTERVAL,
+        }
+    }
+
+    /// Polls until the battery does something. A poll that fails is an
+    /// error, but calling this again carries on polling.
+    pub async fn next_event(&mut self) -> Result<BatteryEvent, anyhow::Error> {
+        loop {
+            if self.polled {
+                sleep(self.interval).await;
+            }
+            self.polled = true;
+            let reading = self
+                .snapshots
+                .next()
+                .await
+                .expect("battery snapshots never end");
+            match reading? {
+                Some(snapshot) => {
+                    if let Some(event) = self.event(&snapshot) {
+                        return Ok(event);
+                    }
+                }
+                None => self.charging = None,
+            }
+        }
+    }
+
+    /// Sends every event to `sink`, until a poll or the sink fails.
+    pub async fn run(mut self, sink: &mut impl EventSink) -> Result<(), anyhow::Error> {
+        loop {
+            let event = self.next_event().await?;
+            sink.send(event).await?;
+        }
+    }
+
+    /// What happened since the last reading, if anything. A reading can
+    /// only give one event, so a battery plugged in already full reports
+    /// charging and then full on the next poll.
+    fn event(&mut self, snapshot: &BatterySnapshot) -> Option<BatteryEvent> {
+        let level = &snapshot.level;
+        let was_charging = self.charging.replace(snapshot.charging());
+        if snapshot.discharging {
+            self.full_reported = false;
+        } else {
+            // Plugged in, so the warnings are re-armed for the next discharge.
+            self.warned = None;
+        }
+
+        if snapshot.charging() && was_charging == Some(false) {
+            Some(BatteryEvent::Charging(level.clone()))
+        } else if !self.full_reported
+            && (snapshot.charging_state == ChargingState::Full
+                || snapshot.charging() && *level >= self.full)
+        {
+            self.full_reported = true;
+            Some(BatteryEvent::Full(level.clone()))
+        } else if snapshot.discharging
+            && *level <= self.critical
+            && self.warned != Some(Warned::Critical)
+        {
+            self.warned = Some(Warned::Critical);
+            Some(BatteryEvent::CriticalReached(level.clone()))
+        } else if snapshot.discharging && *level <= self.low && self.warned.is_none() {
+            self.warned = Some(Warned::Low);
+            Some(BatteryEvent::LowReached(level.clone()))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ChargingState::{Charging, Discharging, Full};
+
+    use super::*;
+    use crate::battery::MockSource;
+
+    /// The events reported for `readings`, until they run out.
+    async fn events(readings: impl IntoIterator<Item = (u8, ChargingState)>) -> Vec<BatteryEvent> {
+        let source = MockSource::new(readings);
+        let mut monitor = BatteryMonitor::builder(&source).build().unwrap();
+        let mut events = Vec::new();
+        while let Ok(event) = monitor.next_event().await {
+            events.push(event);
+        }
+        events
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn discharge_then_charge() {
+        let readings = [
+            (20, Discharging),
+            (15, Discharging),
+            (14, Discharging),
+            (6, Discharging),
+            (5, Discharging),
+            (5, Charging),
+            (100, Full),
+        ];
+        let level = BatteryLevel::new;
+        assert_eq!(
+            events(readings).await,
+            [
+                BatteryEvent::LowReached(level(15)),
+                BatteryEvent::CriticalReached(level(6)),
+                BatteryEvent::Charging(level(5)),
+                BatteryEvent::Full(level(100)),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warnings_rearm_once_plugged_in() {
+        let readings = [(14, Discharging), (14, Charging), (14, Discharging)];
+        let level = BatteryLevel::new;
+        assert_eq!(
+            events(readings).await,
+            [
+                BatteryEvent::LowReached(level(14)),
+                BatteryEvent::Charging(level(14)),
+                BatteryEvent::LowReached(level(14)),
+            ]
+        );
+    }
+
+    #[test]
+    fn critical_must_be_below_low() {
+        let source = MockSource::new([]);
+        let built = BatteryMonitor::builder(&source)
+            .critical(BatteryLevel::new(20))
+            .build();
+        assert!(built.is_err());
+    }
+}
@@ -0,0 +1,504 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use battery_notifier::battery::BatteryLevel;
+use tracing::info;
+
+use crate::config::{Tier, Urgency};
+use crate::governor::NotificationGovernor;
+use crate::messages::{Messages, fill};
+
+/// How many times a critical warning repeats before it's taken as ignored.
+pub const IGNORED_CRITICAL_REPEATS: u32 = 3;
+
+/// How much longer a warning dismissed with [`Action::Dismiss`] waits to
+/// repeat.
+pub const DISMISS_FOR: Duration = Duration::from_secs(30 * 60);
+
+/// Formats an estimated duration as e.g. `~42 min` or `~1h20m`.
+pub fn format_estimate(estimate: Duration) -> String {
+    let minutes = (estimate.as_secs() + 30) / 60;
+    if minutes < 60 {
+        format!("~{minutes} min")
+    } else {
+        format!("~{}h{:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+/// What else is known about the battery when it warns, for the placeholders
+/// in the tier's message.
+#[derive(Default)]
+pub struct WarningDetails {
+    /// The energy left in watt-hours, shown with `--show-energy`.
+    pub energy_wh: Option<f32>,
+    pub remaining: Option<Duration>,
+    pub watts: Option<f32>,
+    pub health_percent: Option<f32>,
+}
+
+/// The icon theme's icon for `level`, rounded down to the nearest 10%.
+fn graded_icon(level: &BatteryLevel) -> String {
+    format!("battery-level-{}", level.level() / 10 * 10)
+}
+
+/// How long a notification stays on screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timeout {
+    /// As long as the notification server decides.
+    Default,
+    /// Until dismissed.
+    Persistent,
+    After(Duration),
+}
+
+/// A button on a warning, shown by the D-Bus backend. libnotify calls an
+/// action back from a GLib main loop, which the daemon doesn't run, so it
+/// shows none.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Runs `--suspend-cmd` straight away.
+    Suspend,
+    /// Holds the warning's repeats back for [`DISMISS_FOR`] longer.
+    Dismiss,
+}
+
+impl Action {
+    /// The key the notification server reports the action by.
+    pub fn key(self) -> &'static str {
+        match self {
+            Action::Suspend => "suspend",
+            Action::Dismiss => "dismiss",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Action> {
+        [Action::Suspend, Action::Dismiss]
+            .into_iter()
+            .find(|action| action.key() == key)
+    }
+}
+
+/// A notification to show, whichever way it is sent.
+#[derive(Clone)]
+pub struct Message {
+    pub summary: String,
+    pub body: String,
+    pub icon: String,
+    pub urgency: Urgency,
+    pub timeout: Timeout,
+    /// The battery level, for servers that draw it as a progress bar.
+    pub value: Option<u8>,
+    /// A themed sound for the server to play, by its name.
+    pub sound: Option<String>,
+    /// The buttons to show, each with its label.
+    pub actions: Vec<(Action, String)>,
+}
+
+/// Sends notifications to the desktop's notification server.
+#[allow(async_fn_in_trait)]
+pub trait Notifier {
+    /// Identifies a notification that was shown, so it can be replaced or
+    /// closed later.
+    type Handle;
+
+    /// Shows `message`, replacing the notification `replaces` if given.
+    async fn show(
+        &self,
+        message: &Message,
+        replaces: Option<&Self::Handle>,
+    ) -> Result<Self::Handle, anyhow::Error>;
+
+    async fn close(&self, handle: &Self::Handle) -> Result<(), anyhow::Error>;
+
+    /// Waits for one of the actions on a notification that was shown with
+    /// some to be picked. Backends that can't show actions never return.
+    async fn action(&self) -> Action {
+        std::future::pending().await
+    }
+}
+
+/// The notifications about the battery, shown through a [`Notifier`].
+pub struct NotificationService<N: Notifier> {
+    notifier: N,
+    graded_icons: bool,
+    progress_hint: bool,
+    messages: Messages,
+    /// The notification last shown for each warning tier, by its level, so a
+    /// repeated warning replaces it rather than adding another.
+    warnings: BTreeMap<BatteryLevel, N::Handle>,
+    /// How many notifications have been shown.
+    sent: Cell<u64>,
+    governor: NotificationGovernor,
+}
+
+impl<N: Notifier> NotificationService<N> {
+    /// With `graded_icons`, notifications about the battery level use
+    /// [`graded_icon`] instead of the icon they are given, and with
+    /// `progress_hint` they show the level as a progress bar. The
+    /// notifications other than warnings take their text from `messages`.
+    /// Only critical ones are shown within `min_gap` of the last, if given.
+    pub fn new(
+        notifier: N,
+        graded_icons: bool,
+        progress_hint: bool,
+        messages: Messages,
+        min_gap: Option<Duration>,
+    ) -> NotificationService<N> {
+        NotificationService {
+            notifier,
+            graded_icons,
+            progress_hint,
+            messages,
+            warnings: BTreeMap::new(),
+            sent: Cell::new(0),
+            governor: NotificationGovernor::new(min_gap),
+        }
+    }
+
+    /// How many notifications have been shown, counting updates in place.
+    pub fn sent(&self) -> u64 {
+        self.sent.get()
+    }
+
+    fn icon(&self, icon: &str, level: &BatteryLevel) -> String {
+        if self.graded_icons {
+            graded_icon(level)
+        } else {
+            icon.to_string()
+        }
+    }
+
+    fn value(&self, level: &BatteryLevel) -> Option<u8> {
+        self.progress_hint.then(|| level.level())
+    }
+
+    /// The notification body for `message`, with the energy left if given.
+    fn with_energy(&self, message: String, energy_wh: Option<f32>) -> String {
+        match energy_wh {
+            Some(wh) => format!(
+                "{message} {}",
+                fill(&self.messages.energy, &[("wh", &format!("{wh:.1}"))])
+            ),
+            None => message,
+        }
+    }
+
+    /// The notification body for `message`, with the time remaining if known.
+    fn with_remaining(&self, message: String, remaining: Option<Duration>) -> String {
+        match remaining {
+            Some(remaining) => {
+                let time = format_estimate(remaining);
+                format!(
+                    "{message} {}",
+                    fill(&self.messages.remaining, &[("time", &time)])
+                )
+            }
+            None => message,
+        }
+    }
+
+    /// Shows `message`, or holds it back for [`show_held`](Self::show_held)
+    /// if it's too soon after the last.
+    async fn show(&self, message: Message) -> Result<(), anyhow::Error> {
+        if !self.governor.may_show(&message) {
+            self.governor.hold(message);
+            return Ok(());
+        }
+        self.show_now(&message).await
+    }
+
+    async fn show_now(&self, message: &Message) -> Result<(), anyhow::Error> {
+        self.notifier.show(message, None).await?;
+        self.governor.shown();
+        self.sent.set(self.sent.get() + 1);
+        Ok(())
+    }
+
+    /// Shows the oldest notification held back by `min_gap`, if it's been
+    /// long enough since the last.
+    pub async fn show_held(&self) -> Result<(), anyhow::Error> {
+        match self.governor.take_due() {
+            Some(message) => self.show_now(&message).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Warns that the battery has fallen to `tier`'s level, updating the
+    /// notification of any earlier warning for the tier in place. The time
+    /// remaining is added unless the message places it itself, and anything
+    /// it places that isn't known reads `unknown`. A critical warning that
+    /// has already repeated [`IGNORED_CRITICAL_REPEATS`] times in a row
+    /// takes a more urgent summary. Returns whether it was shown, as one
+    /// too soon after the last notification isn't, to be warned of again.
+    pub async fn notify_warning(
+        &mut self,
+        tier: &Tier,
+        level: BatteryLevel,
+        details: &WarningDetails,
+        repeats: u32,
+    ) -> Result<bool, anyhow::Error> {
+        let known = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+        let body = fill(
+            &tier.message,
+            &[
+                ("level", &level),
+                (
+                    "time_remaining",
+                    &known(details.remaining.map(format_estimate)),
+                ),
+                (
+                    "watts",
+                    &known(details.watts.map(|watts| format!("{watts:.1}"))),
+                ),
+                (
+                    "health",
+                    &known(details.health_percent.map(|health| format!("{health:.0}"))),
+                ),
+            ],
+        );
+        let body = self.with_energy(body, details.energy_wh);
+        let body = if tier.message.contains("{time_remaining}") {
+            body
+        } else {
+            self.with_remaining(body, details.remaining)
+        };
+        let summary = if tier.urgency == Urgency::Critical && repeats >= IGNORED_CRITICAL_REPEATS {
+            self.messages.ignored_critical_summary.clone()
+        } else {
+            tier.summary.clone()
+        };
+        let message = Message {
+            summary,
+            body,
+            icon: self.icon(&tier.icon, &level),
+            urgency: tier.urgency,
+            timeout: tier.timeout,
+            value: self.value(&level),
+            sound: tier.sound.clone(),
+            actions: vec![
+                (Action::Suspend, self.messages.suspend_action.clone()),
+                (Action::Dismiss, self.messages.dismiss_action.clone()),
+            ],
+        };
+        if !self.governor.may_show(&message) {
+            info!(
+                "Not showing {:?} yet, too soon after the last notification",
+                message.summary
+            );
+            return Ok(false);
+        }
+        let previous = self.warnings.remove(&tier.level);
+        let handle = self.notifier.show(&message, previous.as_ref()).await?;
+        self.governor.shown();
+        self.sent.set(self.sent.get() + 1);
+        self.warnings.insert(tier.level.clone(), handle);
+        Ok(true)
+    }
+
+    /// Waits for an action to be picked on a warning.
+    pub async fn action(&self) -> Action {
+        self.notifier.action().await
+    }
+
+    /// Closes the warnings still on screen, e.g. once charging resumes.
+    pub async fn close_alerts(&mut self) -> Result<(), anyhow::Error> {
+        for handle in std::mem::take(&mut self.warnings).values() {
+            self.notifier.close(handle).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn notify_suspend(
+        &self,
+        level: BatteryLevel,
+        delay: Duration,
+    ) -> Result<(), anyhow::Error> {
+        self.show(Message {
+            summary: self.messages.suspend_summary.clone(),
+            body: fill(
+                &self.messages.suspend_message,
+                &[("level", &level), ("seconds", &delay.as_secs())],
+            ),
+            icon: "battery-empty".to_string(),
+            urgency: Urgency::Critical,
+            timeout: Timeout::After(delay),
+            value: self.value(&level),
+            sound: None,
+            actions: Vec::new(),
+        })
+        .await
+    }
+
+    pub async fn notify_poor_health(&self, health: f32) -> Result<(), anyhow::Error> {
+        self.show(Message {
+            summary: self.messages.worn_summary.clone(),
+            body: fill(
+                &self.messages.worn_message,
+                &[("health", &format!("{health:.0}"))],
+            ),
+            icon: "battery-caution".to_string(),
+            urgency: Urgency::Normal,
+            timeout: Timeout::Default,
+            value: None,
+            sound: None,
+            actions: Vec::new(),
+        })
+        .await
+    }
+
+    pub async fn notify_hot_battery(&self, celsius: f32) -> Result<(), anyhow::Error> {
+        self.show(Message {
+            summary: self.messages.hot_summary.clone(),
+            body: fill(
+                &self.messages.hot_message,
+                &[("celsius", &format!("{celsius:.0}"))],
+            ),
+            icon: "battery-caution".to_string(),
+            urgency: Urgency::Critical,
+            timeout: Timeout::Default,
+            value: None,
+            sound: None,
+            actions: Vec::new(),
+        })
+        .await
+    }
+
+    pub async fn notify_fast_drain(&self, rate: f32) -> Result<(), anyhow::Error> {
+        self.show(Message {
+            summary: self.messages.drain_summary.clone(),
+            body: fill(
+                &self.messages.drain_message,
+                &[("rate", &format!("{rate:.1}"))],
+            ),
+            icon: "battery-caution".to_string(),
+            urgency: Urgency::Normal,
+            timeout: Timeout::Default,
+            value: None,
+            sound: None,
+            actions: Vec::new(),
+        })
+        .await
+    }
+
+    pub async fn notify_slow_charge(&self, watts: f32) -> Result<(), anyhow::Error> {
+        self.show(Message {
+            summary: self.messages.slow_charge_summary.clone(),
+            body: fill(
+                &self.messages.slow_charge_message,
+                &[("watts", &format!("{watts:.1}"))],
+            ),
+            icon: "battery-caution-charging".to_string(),
+            urgency: Urgency::Normal,
+            timeout: Timeout::Default,
+            value: None,
+            sound: None,
+            actions: Vec::new(),
+        })
+        .await
+    }
+
+    pub async fn notify_step(&self, level: BatteryLevel) -> Result<(), anyhow::Error> {
+        self.show(Message {
+            summary: self.messages.step_summary.clone(),
+            body: fill(&self.messages.step_message, &[("level", &level)]),
+            icon: self.icon("battery", &level),
+            urgency: Urgency::Low,
+            timeout: Timeout::Default,
+            value: self.value(&level),
+            sound: None,
+            actions: Vec::new(),
+        })
+        .await
+    }
+
+    pub async fn notify_on_battery(&self, level: BatteryLevel) -> Result<(), anyhow::Error> {
+        self.show(Message {
+            summary: self.messages.unplugged_summary.clone(),
+            body: fill(&self.messages.unplugged_message, &[("level", &level)]),
+            icon: "battery".to_string(),
+            urgency: Urgency::Normal,
+            timeout: Timeout::Default,
+            value: self.value(&level),
+            sound: None,
+            actions: Vec::new(),
+        })
+        .await
+    }
+
+    pub async fn notify_charging(&self, level: BatteryLevel) -> Result<(), anyhow::Error> {
+        self.show(Message {
+            summary: self.messages.charging_summary.clone(),
+            body: fill(&self.messages.charging_message, &[("level", &level)]),
+            icon: self.icon("battery-good-charging", &level),
+            urgency: Urgency::Low,
+            timeout: Timeout::Default,
+            value: self.value(&level),
+            sound: None,
+            actions: Vec::new(),
+        })
+        .await
+    }
+
+    pub async fn notify_charge_limit(&self, level: BatteryLevel) -> Result<(), anyhow::Error> {
+        self.show(Message {
+            summary: self.messages.charge_limit_summary.clone(),
+            body: fill(&self.messages.charge_limit_message, &[("level", &level)]),
+            icon: self.icon("battery-good-charging", &level),
+            urgency: Urgency::Normal,
+            timeout: Timeout::Default,
+            value: self.value(&level),
+            sound: None,
+            actions: Vec::new(),
+        })
+        .await
+    }
+
+    /// Shows the level, and the time left to empty or full if known, for
+    /// `--notify-on-start`. This isn't held back by `min_gap`, nor holds back
+    /// a warning due on the same poll.
+    pub async fn notify_status(
+        &self,
+        level: BatteryLevel,
+        charging: bool,
+        remaining: Option<Duration>,
+    ) -> Result<(), anyhow::Error> {
+        let body = fill(&self.messages.status_message, &[("level", &level)]);
+        let icon = if charging {
+            "battery-good-charging"
+        } else {
+            "battery"
+        };
+        let message = Message {
+            summary: self.messages.status_summary.clone(),
+            body: self.with_remaining(body, remaining),
+            icon: self.icon(icon, &level),
+            urgency: Urgency::Low,
+            timeout: Timeout::Default,
+            value: self.value(&level),
+            sound: None,
+            actions: Vec::new(),
+        };
+        self.notifier.show(&message, None).await?;
+        self.sent.set(self.sent.get() + 1);
+        Ok(())
+    }
+
+    pub async fn notify_full_charge(
+        &self,
+        level: BatteryLevel,
+        icon: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.show(Message {
+            summary: self.messages.full_summary.clone(),
+            body: fill(&self.messages.full_message, &[("level", &level)]),
+            icon: self.icon(icon, &level),
+            urgency: Urgency::Normal,
+            timeout: Timeout::Default,
+            value: self.value(&level),
+            sound: None,
+            actions: Vec::new(),
+        })
+        .await
+    }
+}
@@ -0,0 +1,134 @@
+//! Times of day when only critical warnings are shown.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::{Context, anyhow, bail};
+
+/// A window of the day given as `HH:MM-HH:MM` in local time, which wraps past
+/// midnight if it ends before it starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuietHours {
+    /// Minutes after midnight.
+    start: u16,
+    end: u16,
+}
+
+fn parse_time(time: &str) -> Result<u16, anyhow::Error> {
+    let (hours, minutes) = time
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Expected HH:MM, got {time:?}"))?;
+    let hours: u16 = hours
+        .parse()
+        .with_context(|| format!("Invalid hour in {time:?}"))?;
+    let minutes: u16 = minutes
+        .parse()
+        .with_context(|| format!("Invalid minute in {time:?}"))?;
+    if hours > 23 || minutes > 59 {
+        bail!("{time:?} isn't a time of day");
+    }
+    Ok(hours * 60 + minutes)
+}
+
+impl FromStr for QuietHours {
+    type Err = anyhow::Error;
+
+    fn from_str(window: &str) -> Result<QuietHours, anyhow::Error> {
+        let (start, end) = window
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Expected HH:MM-HH:MM, got {window:?}"))?;
+        let (start, end) = (parse_time(start.trim())?, parse_time(end.trim())?);
+        if start == end {
+            bail!("Quiet hours {window:?} start and end at the same time");
+        }
+        Ok(QuietHours { start, end })
+    }
+}
+
+impl Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+impl QuietHours {
+    fn contains(&self, minute: u16) -> bool {
+        if self.start < self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    /// Whether the local time is within the window.
+    pub fn now(&self) -> bool {
+        local_minute().is_some_and(|minute| self.contains(minute))
+    }
+}
+
+/// The minutes since local midnight, or `None` if the time zone can't be
+/// worked out.
+fn local_minute() -> Option<u16> {
+    // SAFETY: `time` accepts a null pointer, and `localtime_r` only writes to
+    // the `tm` it's given.
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return None;
+        }
+        Some((tm.tm_hour * 60 + tm.tm_min) as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minute(time: &str) -> u16 {
+        parse_time(time).unwrap()
+    }
+
+    #[test]
+    fn within_a_window_in_the_day() {
+        let quiet: QuietHours = "12:30-14:00".parse().unwrap();
+        assert!(!quiet.contains(minute("12:29")));
+        assert!(quiet.contains(minute("12:30")));
+        assert!(quiet.contains(minute("13:59")));
+        assert!(!quiet.contains(minute("14:00")));
+        assert!(!quiet.contains(minute("00:00")));
+    }
+
+    #[test]
+    fn within_a_window_past_midnight() {
+        let quiet: QuietHours = "23:00-07:00".parse().unwrap();
+        assert!(!quiet.contains(minute("22:59")));
+        assert!(quiet.contains(minute("23:00")));
+        assert!(quiet.contains(minute("00:00")));
+        assert!(quiet.contains(minute("06:59")));
+        assert!(!quiet.contains(minute("07:00")));
+        assert!(!quiet.contains(minute("12:00")));
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        let quiet: QuietHours = " 9:05 - 17:30".parse().unwrap();
+        assert_eq!(quiet.to_string(), "09:05-17:30");
+        for window in [
+            "23:00",
+            "24:00-07:00",
+            "07:60-08:00",
+            "07:00-07:00",
+            "a:00-b:00",
+        ] {
+            assert!(window.parse::<QuietHours>().is_err(), "{window:?}");
+        }
+    }
+}
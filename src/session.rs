@@ -0,0 +1,81 @@
+//! A summary of how the battery fared while the daemon ran, logged when it
+//! stops.
+
+use std::time::Instant;
+
+use battery_notifier::battery::BatteryLevel;
+use tracing::info;
+
+use crate::config::Urgency;
+use crate::notification::format_estimate;
+
+/// What was seen since the daemon started.
+pub struct Session {
+    started: Instant,
+    lowest: Option<BatteryLevel>,
+    highest: Option<BatteryLevel>,
+    low_warnings: u32,
+    critical_warnings: u32,
+    /// Estimated from the power draw between readings while discharging.
+    used_wh: f64,
+    /// When the last reading on battery power was, and its draw in watts.
+    last_draw: Option<(Instant, f32)>,
+}
+
+impl Session {
+    /// Starts counting from now.
+    pub fn start() -> Session {
+        Session {
+            started: Instant::now(),
+            lowest: None,
+            highest: None,
+            low_warnings: 0,
+            critical_warnings: 0,
+            used_wh: 0.0,
+            last_draw: None,
+        }
+    }
+
+    /// Records a reading of `level` taken at `at`, drawing `watts` if known.
+    pub fn record(
+        &mut self,
+        at: Instant,
+        level: &BatteryLevel,
+        discharging: bool,
+        watts: Option<f32>,
+    ) {
+        if self.lowest.as_ref().is_none_or(|lowest| level < lowest) {
+            self.lowest = Some(level.clone());
+        }
+        if self.highest.as_ref().is_none_or(|highest| level > highest) {
+            self.highest = Some(level.clone());
+        }
+        if let Some((since, draw)) = self.last_draw {
+            let hours = at.duration_since(since).as_secs_f64() / 3600.0;
+            self.used_wh += f64::from(draw) * hours;
+        }
+        self.last_draw = watts.filter(|_| discharging).map(|watts| (at, watts));
+    }
+
+    /// Counts a warning of `urgency` that was shown.
+    pub fn warned(&mut self, urgency: Urgency) {
+        match urgency {
+            Urgency::Critical => self.critical_warnings += 1,
+            Urgency::Low | Urgency::Normal => self.low_warnings += 1,
+        }
+    }
+
+    pub fn log(&self) {
+        let range = match (&self.lowest, &self.highest) {
+            (Some(lowest), Some(highest)) => format!("battery between {lowest} and {highest}"),
+            _ => "no battery readings".to_string(),
+        };
+        info!(
+            "Session of {}: {range}, {} low and {} critical warnings, ~{:.1} Wh used",
+            format_estimate(self.started.elapsed()),
+            self.low_warnings,
+            self.critical_warnings,
+            self.used_wh
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! Unix signals the daemon responds to while running.
+
+use std::time::{Duration, Instant};
+
+use tokio::signal::unix::{Signal, SignalKind, signal};
+use tokio::sync::watch;
+use tracing::info;
+
+/// Listens for `SIGUSR1`, which snoozes alerts for `duration`, and `SIGUSR2`,
+/// which ends a snooze early. The receiver holds when the snooze ends, if
+/// alerts are snoozed.
+pub fn snooze_signals(
+    duration: Duration,
+) -> Result<watch::Receiver<Option<Instant>>, anyhow::Error> {
+    let mut snooze = signal(SignalKind::user_defined1())?;
+    let mut unsnooze = signal(SignalKind::user_defined2())?;
+    let (sender, receiver) = watch::channel(None);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(()) = snooze.recv() => {
+                    info!("Snoozing alerts for {} minutes", duration.as_secs() / 60);
+                    sender.send_replace(Some(Instant::now() + duration));
+                }
+                Some(()) = unsnooze.recv() => {
+                    info!("Alerts no longer snoozed");
+                    sender.send_replace(None);
+                }
+                else => break,
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+/// Resolves once the daemon is asked to stop, with `SIGTERM` or `SIGINT`.
+pub fn shutdown_signal() -> Result<impl Future<Output = ()>, anyhow::Error> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    Ok(async move {
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = interrupt.recv() => {}
+        }
+    })
+}
+
+/// Receives `SIGHUP`, which asks to reload the config file.
+pub fn reload_signal() -> Result<Signal, anyhow::Error> {
+    Ok(signal(SignalKind::hangup())?)
+}
@@ -0,0 +1,148 @@
+//! A Unix socket where other programs can ask for the latest reading, as
+//! JSON, instead of reading sysfs themselves, or switch the config profile.
+
+use std::fs::{Permissions, remove_file, set_permissions};
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use battery_notifier::battery::BatteryLevel;
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+/// How long to wait after failing to accept a connection before trying
+/// again, so an error that persists, like running out of file descriptors,
+/// doesn't spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// What the monitor last read from the battery.
+pub struct Reading {
+    pub level: BatteryLevel,
+    pub charging: bool,
+    pub at: SystemTime,
+}
+
+/// The latest reading, shared between the monitor and the socket; `None`
+/// before the first reading and while no battery is present.
+pub type LatestReading = Arc<Mutex<Option<Reading>>>;
+
+/// The reply to a client, with the reading's time in seconds since the Unix
+/// epoch, and every field `null` without a reading.
+#[derive(Default, Serialize)]
+struct Reply {
+    level: Option<u8>,
+    charging: Option<bool>,
+    timestamp: Option<u64>,
+}
+
+impl From<Option<&Reading>> for Reply {
+    fn from(reading: Option<&Reading>) -> Reply {
+        match reading {
+            Some(reading) => Reply {
+                level: Some(reading.level.level()),
+                charging: Some(reading.charging),
+                timestamp: Some(
+                    reading
+                        .at
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                ),
+            },
+            None => Reply::default(),
+        }
+    }
+}
+
+/// Names of the config profiles clients asked to switch to.
+pub type ProfileSwitches = mpsc::UnboundedReceiver<String>;
+
+/// Stops listening, and removes the socket, once dropped.
+pub struct Listener {
+    path: PathBuf,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.task.abort();
+        if let Err(e) = remove_file(&self.path) {
+            warn!("Failed to remove {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Listens at `path`, replacing any socket left there by an earlier run, and
+/// answers each line a client sends with the reading in the returned
+/// [`LatestReading`], except for `profile NAME`, which is passed on to the
+/// returned [`ProfileSwitches`]. Only the user may connect, as clients can
+/// switch profiles.
+pub fn serve(path: &Path) -> Result<(LatestReading, ProfileSwitches, Listener), anyhow::Error> {
+    match remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Failed to remove {}", path.display()));
+        }
+        _ => {}
+    }
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("Failed to bind {}", path.display()))?;
+    set_permissions(path, Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict {} to the user", path.display()))?;
+    let latest = LatestReading::default();
+    let (switch, switches) = mpsc::unbounded_channel();
+
+    let shared = latest.clone();
+    let task = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let shared = shared.clone();
+                    let switch = switch.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = answer(stream, &shared, &switch).await {
+                            debug!("Socket client went away: {e}");
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!("Failed to accept a socket connection: {e}");
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                }
+            }
+        }
+    });
+    let listener = Listener {
+        path: path.to_path_buf(),
+        task,
+    };
+    Ok((latest, switches, listener))
+}
+
+async fn answer(
+    stream: UnixStream,
+    latest: &LatestReading,
+    switch: &mpsc::UnboundedSender<String>,
+) -> Result<(), anyhow::Error> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let reply = match line.trim().strip_prefix("profile ") {
+            // The switch is made, or refused, by the monitor, which logs it.
+            Some(name) => {
+                let name = name.trim();
+                switch.send(name.to_string())?;
+                serde_json::json!({ "profile": name }).to_string()
+            }
+            None => serde_json::to_string(&Reply::from(latest.lock().unwrap().as_ref()))?,
+        };
+        writer.write_all(format!("{reply}\n").as_bytes()).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,221 @@
+//! Keeps the notification state in a file so that restarting the daemon
+//! doesn't repeat a warning that was only just shown.
+
+use std::env;
+use std::fs::{read_to_string, write};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use battery_notifier::battery::BatteryLevel;
+use serde::{Deserialize, Serialize};
+
+use crate::NotificationState;
+use crate::config::Tier;
+
+const STATE_FILE: &str = "battery-notifier.state";
+
+/// The saved form of [`NotificationState`]. A warning is identified by its
+/// level, since the tiers may be reconfigured between runs, and times are in
+/// seconds since the Unix epoch.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+enum SavedState {
+    /// Files from before repeats were counted have none.
+    Notified {
+        level: u8,
+        at: u64,
+        #[serde(default)]
+        repeats: u32,
+        /// When a dismissal from the notification ends.
+        #[serde(default)]
+        dismissed_until: Option<u64>,
+    },
+    Charging,
+    /// Files from before the time was kept have none.
+    Full {
+        #[serde(default)]
+        at: Option<u64>,
+    },
+    Suspended,
+    NeverNotified,
+}
+
+/// When `at`, in seconds since the Unix epoch, is as an [`Instant`], if it
+/// isn't before the [`Instant`]s began.
+fn instant(at: u64) -> Option<Instant> {
+    let at = UNIX_EPOCH + Duration::from_secs(at);
+    let (wall, now) = (SystemTime::now(), Instant::now());
+    match at.duration_since(wall) {
+        Ok(ahead) => now.checked_add(ahead),
+        Err(e) => now.checked_sub(e.duration()),
+    }
+}
+
+/// `t` in seconds since the Unix epoch, whether it's past or to come.
+fn epoch_secs(t: Instant) -> u64 {
+    let (wall, now) = (SystemTime::now(), Instant::now());
+    let at = match t.checked_duration_since(now) {
+        Some(ahead) => wall + ahead,
+        None => wall - now.duration_since(t),
+    };
+    at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// The state file under `$XDG_RUNTIME_DIR`, which is cleared at logout, or
+/// `None` if that isn't set.
+pub fn state_path() -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join(STATE_FILE))
+}
+
+/// Reads the state saved at `path`, if any. A warning for a level that is no
+/// longer one of `tiers` is dropped.
+pub fn load(path: &Path, tiers: &[Tier]) -> Result<Option<NotificationState>, anyhow::Error> {
+    let raw = match read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let saved: SavedState =
+        toml::from_str(&raw).with_context(|| format!("Invalid state file {}", path.display()))?;
+
+    Ok(Some(match saved {
+        SavedState::Notified {
+            level,
+            at,
+            repeats,
+            dismissed_until,
+        } => {
+            let tier = tiers
+                .iter()
+                .position(|tier| tier.level == BatteryLevel::saturating(level));
+            // A warning can't have been shown later than now, e.g. if the
+            // clock was set back since.
+            match (tier, instant(at).map(|t| t.min(Instant::now()))) {
+                (Some(tier), Some(t)) => {
+                    NotificationState::Notified(tier, t, repeats, dismissed_until.and_then(instant))
+                }
+                _ => NotificationState::NeverNotified,
+            }
+        }
+        SavedState::Charging => NotificationState::Charging,
+        SavedState::Full { at } => {
+            NotificationState::Full(at.and_then(instant).unwrap_or_else(Instant::now))
+        }
+        SavedState::Suspended => NotificationState::Suspended,
+        SavedState::NeverNotified => NotificationState::NeverNotified,
+    }))
+}
+
+/// Writes `state` to `path`, replacing any saved state.
+pub fn save(path: &Path, state: NotificationState, tiers: &[Tier]) -> Result<(), anyhow::Error> {
+    let saved = match state {
+        NotificationState::Notified(tier, t, repeats, dismissed) => SavedState::Notified {
+            level: tiers[tier].level.level(),
+            at: epoch_secs(t),
+            repeats,
+            dismissed_until: dismissed.map(epoch_secs),
+        },
+        NotificationState::Charging => SavedState::Charging,
+        NotificationState::Full(t) => SavedState::Full {
+            at: Some(epoch_secs(t)),
+        },
+        NotificationState::Suspended => SavedState::Suspended,
+        NotificationState::NeverNotified => SavedState::NeverNotified,
+    };
+    write(path, toml::to_string(&saved)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::{Args, Settings};
+    use crate::notification::DISMISS_FOR;
+
+    fn tiers() -> Vec<Tier> {
+        let args = ["battery-notifier", "--config", "/nonexistent/config.toml"];
+        Settings::load(Args::try_parse_from(args).unwrap())
+            .unwrap()
+            .tiers
+    }
+
+    /// Whether `a` and `b` are the same to the second they're saved in.
+    fn same_second(a: Instant, b: Instant) -> bool {
+        a.max(b).duration_since(a.min(b)) <= Duration::from_secs(1)
+    }
+
+    #[test]
+    fn round_trips() {
+        let (dir, tiers) = (tempfile::tempdir().unwrap(), tiers());
+        let path = dir.path().join(STATE_FILE);
+        let now = Instant::now();
+        let states = [
+            NotificationState::Charging,
+            NotificationState::Suspended,
+            NotificationState::NeverNotified,
+        ];
+        for state in states {
+            save(&path, state, &tiers).unwrap();
+            assert_eq!(load(&path, &tiers).unwrap(), Some(state));
+        }
+
+        let full = now - Duration::from_secs(600);
+        save(&path, NotificationState::Full(full), &tiers).unwrap();
+        match load(&path, &tiers).unwrap() {
+            Some(NotificationState::Full(t)) => assert!(same_second(t, full)),
+            state => panic!("{state:?}"),
+        }
+
+        let (at, until) = (now - Duration::from_secs(60), now + DISMISS_FOR);
+        let notified = NotificationState::Notified(1, at, 2, Some(until));
+        save(&path, notified, &tiers).unwrap();
+        match load(&path, &tiers).unwrap() {
+            Some(NotificationState::Notified(1, t, 2, Some(dismissed))) => {
+                assert!(same_second(t, at));
+                assert!(same_second(dismissed, until));
+            }
+            state => panic!("{state:?}"),
+        }
+    }
+
+    #[test]
+    fn warning_from_the_future_is_taken_as_now() {
+        let (dir, tiers) = (tempfile::tempdir().unwrap(), tiers());
+        let path = dir.path().join(STATE_FILE);
+        let ahead = SystemTime::now() + Duration::from_secs(3600);
+        let at = ahead.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let level = tiers[0].level.level();
+        write(
+            &path,
+            format!("state = \"notified\"\nlevel = {level}\nat = {at}\n"),
+        )
+        .unwrap();
+        match load(&path, &tiers).unwrap() {
+            Some(NotificationState::Notified(0, t, 0, None)) => {
+                assert!(same_second(t, Instant::now()));
+            }
+            state => panic!("{state:?}"),
+        }
+    }
+
+    #[test]
+    fn warning_for_a_level_no_longer_warned_at_is_dropped() {
+        let (dir, tiers) = (tempfile::tempdir().unwrap(), tiers());
+        let path = dir.path().join(STATE_FILE);
+        write(&path, "state = \"notified\"\nlevel = 42\nat = 0\n").unwrap();
+        assert_eq!(
+            load(&path, &tiers).unwrap(),
+            Some(NotificationState::NeverNotified)
+        );
+    }
+
+    #[test]
+    fn missing_file_is_no_state() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(load(&dir.path().join(STATE_FILE), &tiers()).unwrap(), None);
+    }
+}
@@ -0,0 +1,925 @@
+//! Batteries read from the kernel's power supply class in sysfs.
+
+use std::cell::Cell;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use clap::ValueEnum;
+use serde::Deserialize;
+use tokio::fs::{read, read_dir, write};
+use tracing::warn;
+
+use crate::battery::{
+    BatteryInfo, BatteryLevel, BatterySource, CapacityLevel, ChargingState, calc_battery_level,
+};
+
+/// Where the kernel lists the power supplies, unless pointed elsewhere, e.g.
+/// at a fake tree for a demo.
+pub const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+const BATTERY_CHARGING: &str = "status";
+/// What kind of power supply it is, e.g. `Battery` or `Mains`.
+const POWER_SUPPLY_TYPE: &str = "type";
+/// Whether a power supply powers the system or a device such as a wireless
+/// keyboard, where the driver says.
+const POWER_SUPPLY_SCOPE: &str = "scope";
+/// Name prefixes of the AC adapters whose `online` file says whether they
+/// are plugged in.
+const AC_ADAPTERS: [&str; 2] = ["AC", "ADP"];
+const AC_ONLINE: &str = "online";
+/// Files tried in order for a battery's current charge: energy (µWh), then
+/// charge (µAh), then the kernel's own percentage.
+const BATTERY_LEVEL_NOW: [&str; 3] = ["energy_now", "charge_now", BATTERY_CAPACITY];
+/// Files tried in order for a battery's full charge, matching
+/// [`BATTERY_LEVEL_NOW`]; a battery only exposing `capacity` is full at 100.
+const BATTERY_LEVEL_FULL: [&str; 2] = ["energy_full", "charge_full"];
+const BATTERY_CAPACITY: &str = "capacity";
+const BATTERY_CAPACITY_LEVEL: &str = "capacity_level";
+const BATTERY_POWER: &str = "power_now";
+const BATTERY_CURRENT: &str = "current_now";
+const BATTERY_VOLTAGE: &str = "voltage_now";
+/// The battery temperature in tenths of a degree Celsius.
+const BATTERY_TEMP: &str = "temp";
+const BATTERY_CYCLES: &str = "cycle_count";
+/// The levels in percent at which charging starts and stops, where the
+/// driver supports limiting the charge.
+const BATTERY_CHARGE_START: &str = "charge_control_start_threshold";
+const BATTERY_CHARGE_END: &str = "charge_control_end_threshold";
+const BATTERY_MANUFACTURER: &str = "manufacturer";
+const BATTERY_MODEL: &str = "model_name";
+const BATTERY_TECHNOLOGY: &str = "technology";
+/// Pairs of (full charge, design full charge) files tried in order when
+/// computing the battery health.
+const BATTERY_DESIGN: [(&str, &str); 2] = [
+    ("energy_full", "energy_full_design"),
+    ("charge_full", "charge_full_design"),
+];
+/// Sets of (charge, full charge, rate) files tried in order when estimating
+/// the time to empty or full: µWh with µW, or µAh with µA.
+const BATTERY_RATE: [(&str, &str, &str); 2] = [
+    ("energy_now", "energy_full", BATTERY_POWER),
+    ("charge_now", "charge_full", BATTERY_CURRENT),
+];
+
+/// Where the battery level is read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CapacitySource {
+    /// `capacity` for a single battery that exposes it, otherwise `energy`
+    Auto,
+    /// The kernel's `capacity` percentage
+    Capacity,
+    /// The ratio of current to full energy (or charge)
+    Energy,
+}
+
+/// How to tell whether the battery is charging.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChargeSource {
+    /// The battery's own `status`
+    BatteryStatus,
+    /// Whether an AC adapter (`AC*` or `ADP*`) is `online`, with the
+    /// battery's `status` telling full or held at a threshold apart
+    AcOnline,
+}
+
+/// What a battery reporting its status as `Unknown` is taken to be doing,
+/// which some drivers do briefly while switching between charger and
+/// battery.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownStatus {
+    /// Running on battery, so it can warn
+    #[default]
+    Discharging,
+    /// Charging, so it never warns
+    Charging,
+    /// Whatever the batteries were last doing, or discharging before that's
+    /// known
+    Hold,
+}
+
+/// A battery exposed by the kernel under `/sys/class/power_supply`, or any
+/// other power supply read the same way.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Battery {
+    path: PathBuf,
+}
+
+impl Battery {
+    /// The power supply called `name`, e.g. `BAT0`.
+    pub fn new(name: &str) -> Battery {
+        Battery::at(Path::new(POWER_SUPPLY_DIR).join(name))
+    }
+
+    /// The power supply in the directory `path`.
+    pub fn at<P: Into<PathBuf>>(path: P) -> Battery {
+        Battery { path: path.into() }
+    }
+
+    fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    /// The directory listing this power supply, and the AC adapters with it.
+    fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(POWER_SUPPLY_DIR))
+    }
+
+    /// Whether the battery is currently plugged in, which for a hot-swappable
+    /// battery may change at any time.
+    pub fn is_present(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Whether the driver says this powers a device, such as a wireless
+    /// keyboard, rather than the system.
+    pub fn is_device_scoped(&self) -> bool {
+        read_attribute(&self.file(POWER_SUPPLY_SCOPE)).as_deref() == Some("Device")
+    }
+}
+
+/// The trimmed contents of the attribute at `path`, if it can be read.
+fn read_attribute(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|contents| contents.trim().to_string())
+}
+
+/// The names of the batteries under `root` that power the system, sorted,
+/// skipping those scoped to a device, such as a docked keyboard's. Most
+/// system batteries don't give a scope at all.
+pub fn system_batteries(root: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return Vec::new();
+    };
+    let mut names: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let battery = Battery::at(entry.path());
+            read_attribute(&battery.file(POWER_SUPPLY_TYPE)).as_deref() == Some("Battery")
+                && !battery.is_device_scoped()
+        })
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+}
+
+/// The batteries that are currently present.
+fn present(batteries: &[Battery]) -> Vec<Battery> {
+    batteries
+        .iter()
+        .filter(|battery| battery.is_present())
+        .cloned()
+        .collect()
+}
+
+/// The battery's status in lowercase with single spaces, as drivers differ in
+/// case and whitespace, e.g. `not charging`.
+async fn battery_status(battery: &Battery) -> Result<String, anyhow::Error> {
+    let raw_status = String::from_utf8(read(battery.file(BATTERY_CHARGING)).await?)?;
+    Ok(raw_status
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase())
+}
+
+/// `Unknown` is taken as `unknown`, and a status the kernel doesn't document
+/// is taken as plugged in and holding, so it neither warns nor stops the
+/// daemon.
+async fn battery_charging_state(
+    battery: &Battery,
+    unknown: ChargingState,
+) -> Result<ChargingState, anyhow::Error> {
+    Ok(match battery_status(battery).await?.as_str() {
+        "charging" => ChargingState::Charging,
+        "full" => ChargingState::Full,
+        "not charging" => ChargingState::NotCharging,
+        "discharging" => ChargingState::Discharging,
+        "unknown" => unknown,
+        status => {
+            warn!(
+                "Unexpected status {status:?} for {}, assuming plugged in",
+                battery.path.display()
+            );
+            ChargingState::NotCharging
+        }
+    })
+}
+
+/// Charging if any of the batteries is, full if all of them are, not
+/// charging if all are full or holding, and otherwise discharging.
+///
+/// Some drivers also report "Not charging" on battery power, so that is only
+/// believed where no AC adapter says it is unplugged. A battery whose status
+/// is `Unknown` counts as `unknown`.
+pub async fn charging_state(
+    batteries: &[Battery],
+    unknown: ChargingState,
+) -> Result<ChargingState, anyhow::Error> {
+    let (mut full, mut holding) = (true, true);
+    for battery in batteries {
+        match battery_charging_state(battery, unknown).await? {
+            ChargingState::Charging => return Ok(ChargingState::Charging),
+            ChargingState::Discharging => (full, holding) = (false, false),
+            ChargingState::NotCharging => full = false,
+            ChargingState::Full => {}
+        }
+    }
+    Ok(if full {
+        ChargingState::Full
+    } else if holding
+        && let Some(battery) = batteries.first()
+        && ac_online(battery.dir()).await? != Some(false)
+    {
+        ChargingState::NotCharging
+    } else {
+        ChargingState::Discharging
+    })
+}
+
+/// Whether any of the batteries is currently charging, with an `Unknown`
+/// status counting as `unknown`.
+pub async fn battery_charging(
+    batteries: &[Battery],
+    unknown: ChargingState,
+) -> Result<bool, anyhow::Error> {
+    Ok(charging_state(batteries, unknown).await? == ChargingState::Charging)
+}
+
+/// Whether any AC adapter in `dir` is plugged in, or `None` if there's no
+/// adapter.
+async fn ac_online(dir: &Path) -> Result<Option<bool>, anyhow::Error> {
+    let mut found = false;
+    let mut entries = read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        if !AC_ADAPTERS
+            .iter()
+            .any(|prefix| name.to_string_lossy().starts_with(prefix))
+        {
+            continue;
+        }
+        match read_value(&Battery::at(entry.path()), AC_ONLINE).await? {
+            Some(online) if online != 0.0 => return Ok(Some(true)),
+            Some(_) => found = true,
+            None => {}
+        }
+    }
+    Ok(found.then_some(false))
+}
+
+async fn read_value(battery: &Battery, file: &str) -> Result<Option<f32>, anyhow::Error> {
+    let raw_battery_level = match read(battery.file(file)).await {
+        Ok(raw) => String::from_utf8(raw)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    // Some drivers report a negative current or power while discharging.
+    let res: i64 = raw_battery_level.trim().parse()?;
+
+    Ok(Some(res as f32))
+}
+
+/// Reads a text file such as `model_name`, `None` if the battery doesn't
+/// expose it or it is blank.
+async fn read_text(battery: &Battery, file: &str) -> Result<Option<String>, anyhow::Error> {
+    let text = match read(battery.file(file)).await {
+        Ok(raw) => String::from_utf8(raw)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let text = text.trim();
+    Ok((!text.is_empty()).then(|| text.to_string()))
+}
+
+/// Reads the first of `files` that the battery exposes.
+async fn read_first(battery: &Battery, files: &[&str]) -> Result<Option<f32>, anyhow::Error> {
+    for file in files {
+        if let Some(value) = read_value(battery, file).await? {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// Combined full charge of all the batteries.
+///
+/// Each battery reports `energy_full` if present, else `charge_full`, else
+/// 100 when it only exposes `capacity`. Only the ratio to
+/// [`battery_energy_now`] matters, so batteries using different units can
+/// still be combined, although the result is then only approximate.
+pub async fn battery_energy_full(batteries: &[Battery]) -> Result<f32, anyhow::Error> {
+    let mut total = 0.0;
+    for battery in batteries {
+        total += match read_first(battery, &BATTERY_LEVEL_FULL).await? {
+            Some(full) => full,
+            None if battery.file(BATTERY_CAPACITY).exists() => 100.0,
+            None => bail!("No charge information for {}", battery.path.display()),
+        };
+    }
+    Ok(total)
+}
+
+/// Combined current charge of all the batteries, read from `energy_now`,
+/// `charge_now` or `capacity`, whichever each battery exposes first.
+pub async fn battery_energy_now(batteries: &[Battery]) -> Result<f32, anyhow::Error> {
+    let mut total = 0.0;
+    for battery in batteries {
+        total += read_first(battery, &BATTERY_LEVEL_NOW)
+            .await?
+            .ok_or_else(|| anyhow!("No charge information for {}", battery.path.display()))?;
+    }
+    Ok(total)
+}
+
+/// Combined current charge of all the batteries in watt-hours, or `None`
+/// unless each reports `energy_now`, as a charge in µAh can't be converted.
+pub async fn battery_energy_now_wh(batteries: &[Battery]) -> Result<Option<f32>, anyhow::Error> {
+    let mut total = 0.0;
+    for battery in batteries {
+        match read_value(battery, BATTERY_LEVEL_NOW[0]).await? {
+            Some(energy) => total += energy / 1e6,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(total))
+}
+
+/// The level reported by the kernel in `capacity`. Multiple batteries are
+/// combined as an unweighted mean, so this is only exact for a single one.
+async fn battery_capacity(batteries: &[Battery]) -> Result<BatteryLevel, anyhow::Error> {
+    let mut total = 0.0;
+    for battery in batteries {
+        total += read_value(battery, BATTERY_CAPACITY)
+            .await?
+            .ok_or_else(|| anyhow!("No capacity for {}", battery.path.display()))?
+            .min(100.0);
+    }
+    calc_battery_level(total, 100.0 * batteries.len() as f32)
+}
+
+/// The charge, full charge and (unsigned) rate of change of the batteries,
+/// summed using the first set in [`BATTERY_RATE`] each battery exposes.
+async fn read_rate(batteries: &[Battery]) -> Result<Option<(f32, f32, f32)>, anyhow::Error> {
+    let (mut charge, mut full, mut rate) = (0.0, 0.0, 0.0);
+    'batteries: for battery in batteries {
+        for (charge_file, full_file, rate_file) in BATTERY_RATE {
+            if let (Some(battery_charge), Some(battery_full), Some(battery_rate)) = (
+                read_value(battery, charge_file).await?,
+                read_value(battery, full_file).await?,
+                read_value(battery, rate_file).await?,
+            ) {
+                charge += battery_charge;
+                full += battery_full;
+                rate += battery_rate.abs();
+                continue 'batteries;
+            }
+        }
+        return Ok(None);
+    }
+    Ok(Some((charge, full, rate)))
+}
+
+/// Estimated time until the batteries are empty at the current rate of
+/// discharge, or `None` when `charging`, when the rate is unavailable or
+/// when it is zero.
+async fn time_to_empty(
+    batteries: &[Battery],
+    charging: bool,
+) -> Result<Option<Duration>, anyhow::Error> {
+    if charging {
+        return Ok(None);
+    }
+    Ok(read_rate(batteries)
+        .await?
+        .and_then(|(charge, _, rate)| from_hours(charge / rate)))
+}
+
+/// Estimated time until the batteries are full at the current rate of
+/// charge, or `None` when not `charging`, when the rate is unavailable or
+/// when it is zero, as is common just after plugging in.
+async fn time_to_full(
+    batteries: &[Battery],
+    charging: bool,
+) -> Result<Option<Duration>, anyhow::Error> {
+    if !charging {
+        return Ok(None);
+    }
+    Ok(read_rate(batteries)
+        .await?
+        .and_then(|(charge, full, rate)| from_hours((full - charge).max(0.0) / rate)))
+}
+
+/// The combined power flowing into or out of the batteries in watts, from
+/// `power_now` or else the product of `current_now` and `voltage_now`, or
+/// `None` if a battery exposes neither.
+async fn power_draw_watts(batteries: &[Battery]) -> Result<Option<f32>, anyhow::Error> {
+    let mut watts = 0.0;
+    for battery in batteries {
+        if let Some(power) = read_value(battery, BATTERY_POWER).await? {
+            watts += power.abs() / 1e6;
+        } else if let (Some(current), Some(voltage)) = (
+            read_value(battery, BATTERY_CURRENT).await?,
+            read_value(battery, BATTERY_VOLTAGE).await?,
+        ) {
+            watts += (current.abs() / 1e6) * (voltage / 1e6);
+        } else {
+            return Ok(None);
+        }
+    }
+    Ok(Some(watts))
+}
+
+/// The mean voltage of the batteries in volts, or `None` if a battery
+/// doesn't report it.
+async fn battery_voltage(batteries: &[Battery]) -> Result<Option<f32>, anyhow::Error> {
+    let mut volts = 0.0;
+    for battery in batteries {
+        match read_value(battery, BATTERY_VOLTAGE).await? {
+            Some(voltage) => volts += voltage / 1e6,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(volts / batteries.len() as f32))
+}
+
+/// The combined current flowing into or out of the batteries in amps, or
+/// `None` if a battery doesn't report it.
+async fn battery_current(batteries: &[Battery]) -> Result<Option<f32>, anyhow::Error> {
+    let mut amps = 0.0;
+    for battery in batteries {
+        match read_value(battery, BATTERY_CURRENT).await? {
+            Some(current) => amps += current.abs() / 1e6,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(amps))
+}
+
+/// The combined full charge of the batteries as a percentage of what they
+/// were designed to hold, or `None` if a battery doesn't report its design
+/// capacity.
+async fn battery_health_percent(batteries: &[Battery]) -> Result<Option<f32>, anyhow::Error> {
+    let (mut full, mut design) = (0.0, 0.0);
+    'batteries: for battery in batteries {
+        for (full_file, design_file) in BATTERY_DESIGN {
+            if let (Some(battery_full), Some(battery_design)) = (
+                read_value(battery, full_file).await?,
+                read_value(battery, design_file).await?,
+            ) {
+                full += battery_full;
+                design += battery_design;
+                continue 'batteries;
+            }
+        }
+        return Ok(None);
+    }
+    Ok((design > 0.0).then(|| full / design * 100.0))
+}
+
+/// The highest cycle count of the batteries, or `None` if none of them
+/// report it.
+async fn cycle_count(batteries: &[Battery]) -> Result<Option<u32>, anyhow::Error> {
+    let mut highest = None;
+    for battery in batteries {
+        if let Some(cycles) = read_value(battery, BATTERY_CYCLES).await? {
+            highest = highest.max(Some(cycles as u32));
+        }
+    }
+    Ok(highest)
+}
+
+/// Writes `level` to the battery's `file`, explaining the likely cause if
+/// that isn't allowed.
+async fn write_threshold(
+    battery: &Battery,
+    file: &str,
+    level: &BatteryLevel,
+) -> Result<(), anyhow::Error> {
+    let path = battery.file(file);
+    match write(&path, level.level().to_string()).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => Err(anyhow!(
+            "Not allowed to write {}, setting the charge limit needs root",
+            path.display()
+        )),
+        Err(e) => Err(anyhow!(e).context(format!("Failed to write {}", path.display()))),
+    }
+}
+
+/// Makes the battery start charging at `start` and stop at `end`, which
+/// must be above `start`. Fails if the driver doesn't support this.
+pub async fn set_charge_thresholds(
+    battery: &Battery,
+    start: &BatteryLevel,
+    end: &BatteryLevel,
+) -> Result<(), anyhow::Error> {
+    if !battery.file(BATTERY_CHARGE_END).exists() {
+        bail!("{} doesn't support a charge limit", battery.path.display());
+    }
+    let has_start = battery.file(BATTERY_CHARGE_START).exists();
+    // Drivers reject a start threshold at or above the end one, so when
+    // raising the start past the current end, move the end first.
+    let current_end = read_value(battery, BATTERY_CHARGE_END).await?;
+    if has_start && current_end.is_some_and(|current| current > start.level() as f32) {
+        write_threshold(battery, BATTERY_CHARGE_START, start).await?;
+        write_threshold(battery, BATTERY_CHARGE_END, end).await?;
+    } else {
+        write_threshold(battery, BATTERY_CHARGE_END, end).await?;
+        if has_start {
+            write_threshold(battery, BATTERY_CHARGE_START, start).await?;
+        }
+    }
+    Ok(())
+}
+
+/// The battery's manufacturer, model and technology, as far as known.
+pub async fn battery_info(battery: &Battery) -> Result<BatteryInfo, anyhow::Error> {
+    Ok(BatteryInfo {
+        manufacturer: read_text(battery, BATTERY_MANUFACTURER).await?,
+        model_name: read_text(battery, BATTERY_MODEL).await?,
+        technology: read_text(battery, BATTERY_TECHNOLOGY).await?,
+    })
+}
+
+/// The most severe of the batteries' `capacity_level`, or `None` if none
+/// of them gives a known one.
+pub async fn capacity_level(batteries: &[Battery]) -> Result<Option<CapacityLevel>, anyhow::Error> {
+    let mut lowest = None;
+    for battery in batteries {
+        let level = match read_text(battery, BATTERY_CAPACITY_LEVEL).await?.as_deref() {
+            Some("Critical") => CapacityLevel::Critical,
+            Some("Low") => CapacityLevel::Low,
+            Some("Normal") => CapacityLevel::Normal,
+            Some("High") => CapacityLevel::High,
+            Some("Full") => CapacityLevel::Full,
+            _ => continue,
+        };
+        lowest = Some(lowest.map_or(level, |lowest: CapacityLevel| lowest.min(level)));
+    }
+    Ok(lowest)
+}
+
+/// The temperature of the hottest battery in degrees Celsius, or `None` if
+/// none of them report it.
+async fn battery_temperature_c(batteries: &[Battery]) -> Result<Option<f32>, anyhow::Error> {
+    let mut hottest: Option<f32> = None;
+    for battery in batteries {
+        if let Some(temp) = read_value(battery, BATTERY_TEMP).await? {
+            hottest = Some(hottest.map_or(temp / 10.0, |hottest| hottest.max(temp / 10.0)));
+        }
+    }
+    Ok(hottest)
+}
+
+/// Converts a number of hours, `None` if it is infinite or undefined because
+/// the rate it was computed from was zero.
+fn from_hours(hours: f32) -> Option<Duration> {
+    Duration::try_from_secs_f32(hours * 3600.0).ok()
+}
+
+/// Reads the configured batteries from sysfs, skipping any that are absent.
+pub struct SysfsSource {
+    /// The directory the batteries are listed in.
+    root: PathBuf,
+    batteries: Vec<Battery>,
+    capacity_source: CapacitySource,
+    charge_source: ChargeSource,
+    unknown_status: UnknownStatus,
+    /// What the batteries were last found doing, for [`UnknownStatus::Hold`].
+    last_state: Cell<Option<ChargingState>>,
+}
+
+impl SysfsSource {
+    /// Reads the batteries in `names` under `root`, normally
+    /// [`POWER_SUPPLY_DIR`]. Fails if none of them is present to begin with.
+    pub fn new(
+        root: &Path,
+        names: &[String],
+        capacity_source: CapacitySource,
+        charge_source: ChargeSource,
+        unknown_status: UnknownStatus,
+    ) -> Result<Self, anyhow::Error> {
+        let batteries: Vec<_> = names
+            .iter()
+            .map(|name| Battery::at(root.join(name)))
+            .collect();
+        if present(&batteries).is_empty() {
+            let paths: Vec<_> = batteries
+                .iter()
+                .map(|battery| battery.path.display().to_string())
+                .collect();
+            bail!("No battery found at {}", paths.join(", "));
+        }
+        for battery in batteries
+            .iter()
+            .filter(|battery| battery.is_device_scoped())
+        {
+            warn!(
+                "{} powers a device, not the system, so its level may not be the system's",
+                battery.path.display()
+            );
+        }
+        Ok(SysfsSource {
+            root: root.to_path_buf(),
+            batteries,
+            capacity_source,
+            charge_source,
+            unknown_status,
+            last_state: Cell::new(None),
+        })
+    }
+
+    fn present(&self) -> Vec<Battery> {
+        present(&self.batteries)
+    }
+
+    /// Whether the AC adapter is plugged in, if configured to check it and
+    /// there is one.
+    async fn ac_online(&self) -> Result<Option<bool>, anyhow::Error> {
+        match self.charge_source {
+            ChargeSource::BatteryStatus => Ok(None),
+            ChargeSource::AcOnline => ac_online(&self.root).await,
+        }
+    }
+
+    /// The batteries' state with the AC adapter `online` or not. Plugged
+    /// in, their own status is only believed for being full or held at a
+    /// threshold.
+    async fn on_ac(&self, online: bool) -> Result<ChargingState, anyhow::Error> {
+        if !online {
+            return Ok(ChargingState::Discharging);
+        }
+        Ok(
+            match charging_state(&self.present(), self.unknown()).await? {
+                state @ (ChargingState::Full | ChargingState::NotCharging) => state,
+                _ => ChargingState::Charging,
+            },
+        )
+    }
+
+    /// What a battery with an `Unknown` status counts as.
+    fn unknown(&self) -> ChargingState {
+        match self.unknown_status {
+            UnknownStatus::Discharging => ChargingState::Discharging,
+            UnknownStatus::Charging => ChargingState::Charging,
+            UnknownStatus::Hold => self.last_state.get().unwrap_or(ChargingState::Discharging),
+        }
+    }
+}
+
+impl BatterySource for SysfsSource {
+    fn present(&self) -> Vec<String> {
+        self.present()
+            .iter()
+            .map(|battery| battery.path.display().to_string())
+            .collect()
+    }
+
+    async fn charging(&self) -> Result<bool, anyhow::Error> {
+        match self.ac_online().await? {
+            Some(online) => Ok(self.on_ac(online).await? == ChargingState::Charging),
+            None => battery_charging(&self.present(), self.unknown()).await,
+        }
+    }
+
+    /// Judged from [`charging_state`](BatterySource::charging_state), so a
+    /// battery whose status is `Unknown` is discharging only if
+    /// `--unknown-status` says it is.
+    async fn discharging(&self) -> Result<bool, anyhow::Error> {
+        Ok(self.charging_state().await? == ChargingState::Discharging)
+    }
+
+    async fn charging_state(&self) -> Result<ChargingState, anyhow::Error> {
+        match self.ac_online().await? {
+            Some(online) => self.on_ac(online).await,
+            None => {
+                let state = charging_state(&self.present(), self.unknown()).await?;
+                self.last_state.set(Some(state));
+                Ok(state)
+            }
+        }
+    }
+
+    async fn energy_now(&self) -> Result<f32, anyhow::Error> {
+        battery_energy_now(&self.present()).await
+    }
+
+    async fn energy_full(&self) -> Result<f32, anyhow::Error> {
+        battery_energy_full(&self.present()).await
+    }
+
+    async fn capacity(&self) -> Result<Option<BatteryLevel>, anyhow::Error> {
+        let present = self.present();
+        let use_capacity = match self.capacity_source {
+            CapacitySource::Capacity => true,
+            CapacitySource::Energy => false,
+            CapacitySource::Auto => {
+                present.len() == 1 && present[0].file(BATTERY_CAPACITY).exists()
+            }
+        };
+        if !use_capacity {
+            return Ok(None);
+        }
+        battery_capacity(&present).await.map(Some)
+    }
+
+    async fn energy_now_wh(&self) -> Result<Option<f32>, anyhow::Error> {
+        battery_energy_now_wh(&self.present()).await
+    }
+
+    async fn power_draw_watts(&self) -> Result<Option<f32>, anyhow::Error> {
+        power_draw_watts(&self.present()).await
+    }
+
+    async fn voltage(&self) -> Result<Option<f32>, anyhow::Error> {
+        battery_voltage(&self.present()).await
+    }
+
+    async fn current(&self) -> Result<Option<f32>, anyhow::Error> {
+        battery_current(&self.present()).await
+    }
+
+    async fn time_to_empty(&self) -> Result<Option<Duration>, anyhow::Error> {
+        let charging = self.charging().await?;
+        time_to_empty(&self.present(), charging).await
+    }
+
+    async fn time_to_full(&self) -> Result<Option<Duration>, anyhow::Error> {
+        let charging = self.charging().await?;
+        time_to_full(&self.present(), charging).await
+    }
+
+    async fn health_percent(&self) -> Result<Option<f32>, anyhow::Error> {
+        battery_health_percent(&self.present()).await
+    }
+
+    async fn cycle_count(&self) -> Result<Option<u32>, anyhow::Error> {
+        cycle_count(&self.present()).await
+    }
+
+    async fn temperature_c(&self) -> Result<Option<f32>, anyhow::Error> {
+        battery_temperature_c(&self.present()).await
+    }
+
+    async fn info(&self) -> Result<Option<BatteryInfo>, anyhow::Error> {
+        match self.present().first() {
+            Some(battery) => Ok(Some(battery_info(battery).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn capacity_level(&self) -> Result<Option<CapacityLevel>, anyhow::Error> {
+        capacity_level(&self.present()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A battery directory `name` under `root`, holding `files` with their
+    /// contents.
+    fn battery_with(root: &Path, name: &str, files: &[(&str, &str)]) -> Battery {
+        let dir = root.join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+        Battery::at(dir)
+    }
+
+    fn battery_with_status(root: &Path, name: &str, status: &str) -> Battery {
+        battery_with(root, name, &[(BATTERY_CHARGING, status)])
+    }
+
+    #[tokio::test]
+    async fn statuses() {
+        let root = tempfile::tempdir().unwrap();
+        let cases = [
+            ("Charging\n", ChargingState::Charging),
+            ("Discharging\n", ChargingState::Discharging),
+            ("Not charging\n", ChargingState::NotCharging),
+            ("Full\n", ChargingState::Full),
+            ("Unknown\n", ChargingState::Charging),
+            ("DISCHARGING", ChargingState::Discharging),
+            ("  full \n", ChargingState::Full),
+            ("Not  Charging\n", ChargingState::NotCharging),
+            ("unknown", ChargingState::Charging),
+            // Not one the kernel documents, so taken as plugged in.
+            ("Balancing\n", ChargingState::NotCharging),
+        ];
+        for (index, (status, expected)) in cases.into_iter().enumerate() {
+            let battery = battery_with_status(root.path(), &format!("status-{index}"), status);
+            let state = battery_charging_state(&battery, ChargingState::Charging)
+                .await
+                .unwrap();
+            assert_eq!(state, expected, "status {status:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn status_is_normalized() {
+        let root = tempfile::tempdir().unwrap();
+        let battery = battery_with_status(root.path(), "normalized", " Not \t Charging\n");
+        assert_eq!(battery_status(&battery).await.unwrap(), "not charging");
+    }
+
+    #[tokio::test]
+    async fn unknown_takes_the_given_state() {
+        let root = tempfile::tempdir().unwrap();
+        let battery = battery_with_status(root.path(), "unknown", "Unknown\n");
+        for unknown in [ChargingState::Charging, ChargingState::Discharging] {
+            let state = battery_charging_state(&battery, unknown).await.unwrap();
+            assert_eq!(state, unknown);
+        }
+    }
+
+    #[tokio::test]
+    async fn ac_online_with_the_battery_status() {
+        let root = tempfile::tempdir().unwrap();
+        let cases = [
+            ("1\n", "Charging\n", ChargingState::Charging),
+            // A status some drivers get wrong while plugged in.
+            ("1\n", "Discharging\n", ChargingState::Charging),
+            ("1\n", "Full\n", ChargingState::Full),
+            ("1\n", "Not charging\n", ChargingState::NotCharging),
+            ("0\n", "Charging\n", ChargingState::Discharging),
+        ];
+        for (index, (online, status, expected)) in cases.into_iter().enumerate() {
+            let battery = battery_with_status(root.path(), &format!("ac-{index}/BAT0"), status);
+            battery_with(
+                root.path(),
+                &format!("ac-{index}/AC"),
+                &[(AC_ONLINE, online)],
+            );
+            let source = SysfsSource::new(
+                battery.dir(),
+                &["BAT0".to_string()],
+                CapacitySource::Auto,
+                ChargeSource::AcOnline,
+                UnknownStatus::Discharging,
+            )
+            .unwrap();
+            let state = source.charging_state().await.unwrap();
+            assert_eq!(state, expected, "online {online:?}, status {status:?}");
+            assert_eq!(
+                source.charging().await.unwrap(),
+                expected == ChargingState::Charging
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn charge_is_read_from_energy_then_charge_then_capacity() {
+        let root = tempfile::tempdir().unwrap();
+        let energy = battery_with(
+            root.path(),
+            "energy",
+            &[
+                ("energy_now", "30000000\n"),
+                ("energy_full", "60000000\n"),
+                ("charge_now", "1000000\n"),
+                ("charge_full", "4000000\n"),
+                (BATTERY_CAPACITY, "10\n"),
+            ],
+        );
+        let charge = battery_with(
+            root.path(),
+            "charge",
+            &[
+                ("charge_now", "1000000\n"),
+                ("charge_full", "4000000\n"),
+                (BATTERY_CAPACITY, "10\n"),
+            ],
+        );
+        let capacity = battery_with(root.path(), "capacity", &[(BATTERY_CAPACITY, "10\n")]);
+        let cases = [
+            (energy, 3e7, 6e7),
+            (charge, 1e6, 4e6),
+            (capacity, 10.0, 100.0),
+        ];
+        for (battery, now, full) in cases {
+            let batteries = [battery];
+            assert_eq!(battery_energy_now(&batteries).await.unwrap(), now);
+            assert_eq!(battery_energy_full(&batteries).await.unwrap(), full);
+        }
+    }
+
+    #[tokio::test]
+    async fn no_charge_at_all_is_an_error() {
+        let root = tempfile::tempdir().unwrap();
+        let batteries = [battery_with(
+            root.path(),
+            "no-charge",
+            &[(BATTERY_CHARGING, "Full\n")],
+        )];
+        assert!(battery_energy_now(&batteries).await.is_err());
+        assert!(battery_energy_full(&batteries).await.is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Batteries read from UPower's `DisplayDevice` over D-Bus, which combines
+//! all of them into one and estimates the time left itself.
+
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+
+use crate::battery::{BatteryLevel, BatterySource, CapacityLevel, ChargingState};
+
+#[zbus::proxy(
+    interface = "org.freedesktop.UPower.Device",
+    default_service = "org.freedesktop.UPower",
+    default_path = "/org/freedesktop/UPower/devices/DisplayDevice"
+)]
+trait Device {
+    #[zbus(property)]
+    fn is_present(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn state(&self) -> zbus::Result<u32>;
+    #[zbus(property)]
+    fn percentage(&self) -> zbus::Result<f64>;
+    #[zbus(property)]
+    fn energy(&self) -> zbus::Result<f64>;
+    #[zbus(property)]
+    fn energy_full(&self) -> zbus::Result<f64>;
+    #[zbus(property)]
+    fn energy_rate(&self) -> zbus::Result<f64>;
+    #[zbus(property)]
+    fn voltage(&self) -> zbus::Result<f64>;
+    #[zbus(property)]
+    fn time_to_empty(&self) -> zbus::Result<i64>;
+    #[zbus(property)]
+    fn time_to_full(&self) -> zbus::Result<i64>;
+    #[zbus(property)]
+    fn capacity(&self) -> zbus::Result<f64>;
+    #[zbus(property)]
+    fn temperature(&self) -> zbus::Result<f64>;
+    #[zbus(property)]
+    fn battery_level(&self) -> zbus::Result<u32>;
+}
+
+/// Values of the device's `State` property.
+const STATE_CHARGING: u32 = 1;
+const STATE_DISCHARGING: u32 = 2;
+const STATE_EMPTY: u32 = 3;
+const STATE_FULLY_CHARGED: u32 = 4;
+const STATE_PENDING_CHARGE: u32 = 5;
+const STATE_PENDING_DISCHARGE: u32 = 6;
+
+/// Values of the device's `BatteryLevel` property, for devices that only
+/// report a coarse level.
+const LEVEL_LOW: u32 = 3;
+const LEVEL_CRITICAL: u32 = 4;
+const LEVEL_NORMAL: u32 = 6;
+const LEVEL_HIGH: u32 = 7;
+const LEVEL_FULL: u32 = 8;
+
+/// Zero, which UPower uses for unknown, as `None`.
+fn known(value: f64) -> Option<f64> {
+    (value > 0.0).then_some(value)
+}
+
+/// A duration in seconds, with zero as `None`.
+fn known_secs(secs: i64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs as u64))
+}
+
+/// Reads the batteries through UPower instead of sysfs.
+pub struct UPowerSource {
+    /// Its properties are cached, and kept up to date from the signals UPower
+    /// sends as they change, so reading them doesn't wait on the bus.
+    device: DeviceProxy<'static>,
+}
+
+impl UPowerSource {
+    /// Fails if UPower can't be reached or reports no battery to begin with.
+    pub async fn new() -> Result<Self, anyhow::Error> {
+        let connection = zbus::Connection::system()
+            .await
+            .context("Failed to connect to the system bus")?;
+        let device = DeviceProxy::new(&connection).await?;
+        if !device
+            .is_present()
+            .await
+            .context("Failed to read UPower's IsPresent")?
+        {
+            bail!("UPower reports no battery");
+        }
+        Ok(UPowerSource { device })
+    }
+
+    async fn state(&self) -> Result<u32, anyhow::Error> {
+        self.device
+            .state()
+            .await
+            .context("Failed to read UPower's State")
+    }
+}
+
+impl BatterySource for UPowerSource {
+    /// From the cached `IsPresent`, as the trait can't wait here.
+    fn present(&self) -> Vec<String> {
+        match self.device.cached_is_present() {
+            Ok(Some(true)) => vec![self.device.inner().path().to_string()],
+            _ => Vec::new(),
+        }
+    }
+
+    async fn charging(&self) -> Result<bool, anyhow::Error> {
+        Ok(self.state().await? == STATE_CHARGING)
+    }
+
+    async fn discharging(&self) -> Result<bool, anyhow::Error> {
+        Ok(matches!(
+            self.state().await?,
+            STATE_DISCHARGING | STATE_EMPTY | STATE_PENDING_DISCHARGE
+        ))
+    }
+
+    async fn charging_state(&self) -> Result<ChargingState, anyhow::Error> {
+        Ok(match self.state().await? {
+            STATE_CHARGING => ChargingState::Charging,
+            STATE_FULLY_CHARGED => ChargingState::Full,
+            STATE_DISCHARGING | STATE_EMPTY | STATE_PENDING_DISCHARGE => ChargingState::Discharging,
+            STATE_PENDING_CHARGE => ChargingState::NotCharging,
+            // Unknown, and so not known to be discharging.
+            _ => ChargingState::NotCharging,
+        })
+    }
+
+    async fn energy_now(&self) -> Result<f32, anyhow::Error> {
+        Ok(self.device.energy().await? as f32)
+    }
+
+    async fn energy_full(&self) -> Result<f32, anyhow::Error> {
+        Ok(self.device.energy_full().await? as f32)
+    }
+
+    /// UPower's own percentage, which already accounts for every battery.
+    /// It's rounded down like the kernel's `capacity`, so 14.6% is still
+    /// below a 15% threshold.
+    async fn capacity(&self) -> Result<Option<BatteryLevel>, anyhow::Error> {
+        let percent = self.device.percentage().await?;
+        Ok(Some(BatteryLevel::saturating(
+            percent.floor().clamp(0.0, 100.0) as u8,
+        )))
+    }
+
+    async fn energy_now_wh(&self) -> Result<Option<f32>, anyhow::Error> {
+        Ok(Some(self.device.energy().await? as f32))
+    }
+
+    async fn power_draw_watts(&self) -> Result<Option<f32>, anyhow::Error> {
+        Ok(known(self.device.energy_rate().await?).map(|watts| watts as f32))
+    }
+
+    async fn voltage(&self) -> Result<Option<f32>, anyhow::Error> {
+        Ok(known(self.device.voltage().await?).map(|volts| volts as f32))
+    }
+
+    async fn time_to_empty(&self) -> Result<Option<Duration>, anyhow::Error> {
+        Ok(known_secs(self.device.time_to_empty().await?))
+    }
+
+    async fn time_to_full(&self) -> Result<Option<Duration>, anyhow::Error> {
+        Ok(known_secs(self.device.time_to_full().await?))
+    }
+
+    async fn health_percent(&self) -> Result<Option<f32>, anyhow::Error> {
+        Ok(known(self.device.capacity().await?).map(|percent| percent as f32))
+    }
+
+    async fn temperature_c(&self) -> Result<Option<f32>, anyhow::Error> {
+        Ok(known(self.device.temperature().await?).map(|celsius| celsius as f32))
+    }
+
+    async fn capacity_level(&self) -> Result<Option<CapacityLevel>, anyhow::Error> {
+        Ok(match self.device.battery_level().await? {
+            LEVEL_CRITICAL => Some(CapacityLevel::Critical),
+            LEVEL_LOW => Some(CapacityLevel::Low),
+            LEVEL_NORMAL => Some(CapacityLevel::Normal),
+            LEVEL_HIGH => Some(CapacityLevel::High),
+            LEVEL_FULL => Some(CapacityLevel::Full),
+            // Unknown, or none as the device reports a percentage instead.
+            _ => None,
+        })
+    }
+}
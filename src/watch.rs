@@ -0,0 +1,66 @@
+//! The `--watch` view, redrawing the state the monitor decided on at each
+//! poll in the terminal, to show why a notification did or didn't fire.
+
+use std::io::{Write, stdout};
+use std::time::Duration;
+
+use battery_notifier::battery::{BatteryLevel, ChargingState};
+
+use crate::notification::format_estimate;
+
+/// Clears the terminal and moves the cursor to the top left.
+const CLEAR: &str = "\x1b[2J\x1b[H";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// What one poll read and decided.
+pub struct Snapshot<'a> {
+    pub level: &'a BatteryLevel,
+    pub charging_state: ChargingState,
+    pub watts: Option<f32>,
+    pub time_to_empty: Option<Duration>,
+    pub time_to_full: Option<Duration>,
+    /// The monitor's notification state, described for people.
+    pub notification_state: String,
+    pub snoozed: bool,
+    pub quiet: bool,
+    /// Notifications that would have been shown so far.
+    pub notifications: u64,
+    pub next_poll: Duration,
+}
+
+fn or_unknown<T>(value: Option<T>, format: impl FnOnce(T) -> String) -> String {
+    value.map_or_else(|| "unknown".to_string(), format)
+}
+
+pub fn draw(snapshot: &Snapshot) {
+    let rows = [
+        ("Level", snapshot.level.to_string()),
+        ("Charging", format!("{:?}", snapshot.charging_state)),
+        (
+            "Power draw",
+            or_unknown(snapshot.watts, |watts| format!("{watts:.1} W")),
+        ),
+        (
+            "Time to empty",
+            or_unknown(snapshot.time_to_empty, format_estimate),
+        ),
+        (
+            "Time to full",
+            or_unknown(snapshot.time_to_full, format_estimate),
+        ),
+        ("State", snapshot.notification_state.clone()),
+        ("Snoozed", snapshot.snoozed.to_string()),
+        ("Quiet hours", snapshot.quiet.to_string()),
+        ("Notifications", snapshot.notifications.to_string()),
+        ("Next poll", format!("in {}s", snapshot.next_poll.as_secs())),
+    ];
+    let mut screen = format!("{CLEAR}{BOLD}battery-notifier --watch{RESET} (not notifying)\n\n");
+    for (name, value) in rows {
+        screen += &format!("{name:>14}: {value}\n");
+    }
+    let mut out = stdout().lock();
+    // Nothing sensible to do if the terminal has gone away.
+    let _ = out.write_all(screen.as_bytes());
+    let _ = out.flush();
+}